@@ -3,69 +3,243 @@ grammar LOLCODE;
 HAI	:	'#HAI' | '#hai';
 KTHXBYE	:	'#KTHXBYE' | '#kthxbye';
 OBTW	:	'#OBTW' | '#obtw';
+MAEK_INFO
+	:	'#MAEK INFO' | '#maek info';
+GIMMEH_AUTHOR
+	:	'#GIMMEH AUTHOR' | '#gimmeh author';
+GIMMEH_DATE
+	:	'#GIMMEH DATE' | '#gimmeh date';
+GIMMEH_DESCRIPSHUN
+	:	'#GIMMEH DESCRIPSHUN' | '#gimmeh descripshun';
+GIMMEH_KEYWURDZ
+	:	'#GIMMEH KEYWURDZ' | '#gimmeh keywurdz';
+GIMMEH_TAGZ
+	:	'#GIMMEH TAGZ' | '#gimmeh tagz';
 MAEK_HEAD
 	:	'#MAEK HEAD' | '#maek head';
+GIMMEH_STYLESHEET
+	:	'#GIMMEH STYLESHEET' | '#gimmeh stylesheet';
+MAEK_STYLE
+	:	'#MAEK STYLE' | '#maek style';
+GIMMEH_SCRIPT
+	:	'#GIMMEH SCRIPT' | '#gimmeh script';
+MAEK_SCRIPT
+	:	'#MAEK SCRIPT' | '#maek script';
+GIMMEH_ICON
+	:	'#GIMMEH ICON' | '#gimmeh icon';
+GIMMEH_META
+	:	'#GIMMEH META' | '#gimmeh meta';
 GIMMEH_TITLE : '#GIMMEH TITLE' | '#gimmeh title';
 MKAY	:	'#MKAY' | '#mkay';
+MAEK_HEADR
+	:	'#MAEK HEADR' | '#maek headr';
 MAEK_PARAGRAF
 	:	'#MAEK PARAGRAF' | '#maek paragraf';
 MAEK_LIST
 	:	'#MAEK LIST' | '#maek list';
+MAEK_NUMBRD_LIST
+	:	'#MAEK NUMBRD LIST' | '#maek numbrd list';
 
 OIC	:	'#OIC' | '#oic';
 GIMMEH_BOLD
 	:	'#GIMMEH BOLD' | '#gimmeh bold';
 GIMMEH_ITALICS 
 	:	'#GIMMEH ITALICS' | '#gimmeh italics';
+GIMMEH_KODE
+	:	'#GIMMEH KODE' | '#gimmeh kode';
+GIMMEH_STRIKE
+	:	'#GIMMEH STRIKE' | '#gimmeh strike';
+GIMMEH_UNDERLYNE
+	:	'#GIMMEH UNDERLYNE' | '#gimmeh underlyne';
+GIMMEH_SUBSKRIPT
+	:	'#GIMMEH SUBSKRIPT' | '#gimmeh subskript';
+GIMMEH_SUPRSKRIPT
+	:	'#GIMMEH SUPRSKRIPT' | '#gimmeh suprskript';
+GIMMEH_HILITE
+	:	'#GIMMEH HILITE' | '#gimmeh hilite';
 GIMMEH_ITEM
 	:	'#GIMMEH ITEM' | '#gimmeh item';
+MAEK_QUOTE
+	:	'#MAEK QUOTE' | '#maek quote';
+GIMMEH_CITE
+	:	'#GIMMEH CITE' | '#gimmeh cite';
+GIMMEH_LINE
+	:	'#GIMMEH LINE' | '#gimmeh line';
+MAEK_KODEZ
+	:	'#MAEK KODEZ' | '#maek kodez';
+MAEK_TABUL
+	:	'#MAEK TABUL' | '#maek tabul';
+GIMMEH_HEDR_ROW
+	:	'#GIMMEH HEDR ROW' | '#gimmeh hedr row';
+GIMMEH_ROW
+	:	'#GIMMEH ROW' | '#gimmeh row';
+GIMMEH_CELL
+	:	'#GIMMEH CELL' | '#gimmeh cell';
 GIMMEH_NEWLINE
 	:	'#GIMMEH NEWLINE' | '#gimmeh newline';
 GIMMEH_SOUNDZ
 	:	'#GIMMEH SOUNDZ' | '#gimmeh soundz';
 GIMMEH_VIDZ
 	:	'#GIMMEH VIDZ' | '#gimmeh vidz';
+GIMMEH_LINX
+	:	'#GIMMEH LINX' | '#gimmeh linx';
+GIMMEH_PIKCHUR
+	:	'#GIMMEH PIKCHUR' | '#gimmeh pikchur';
+GIMMEH_TOC
+	:	'#GIMMEH TOC' | '#gimmeh toc';
 I_HAZ	:	'#I HAZ' | '#i haz';
+I_ALWAYS_HAZ
+	:	'#I ALWAYS HAZ' | '#i always haz';
+NAO	:	'#NAO' | '#nao';
+R	:	'#R' | '#r';
 IT_IZ	:	'#IT IZ' | '#it iz';
+HOW_IZ_I
+	:	'#HOW IZ I' | '#how iz i';
+YR	:	'#YR' | '#yr';
+AN_YR	:	'#AN YR' | '#an yr';
+IF_U_SAY_SO
+	:	'#IF U SAY SO' | '#if u say so';
+I_IZ	:	'#I IZ' | '#i iz';
+CAN_HAS	:	'#CAN HAS' | '#can has';
+O_RLY	:	'#O RLY' | '#o rly';
+YA_RLY	:	'#YA RLY' | '#ya rly';
+NO_WAI	:	'#NO WAI' | '#no wai';
+IM_IN_YR
+	:	'#IM IN YR' | '#im in yr';
+OV	:	'#OV' | '#ov';
+FRUM	:	'#FRUM' | '#frum';
+TIL	:	'#TIL' | '#til';
+IM_OUTTA_YR
+	:	'#IM OUTTA YR' | '#im outta yr';
+SUM_OF	:	'#SUM OF' | '#sum of';
+DIFF_OF	:	'#DIFF OF' | '#diff of';
+PRODUKT_OF
+	:	'#PRODUKT OF' | '#produkt of';
+SMOOSH	:	'#SMOOSH' | '#smoosh';
+BOTH_SAEM
+	:	'#BOTH SAEM' | '#both saem';
+AN	:	'#AN' | '#an';
 LEMME_SEE
 	:	'#LEMME SEE' | '#lemme see';
 TLDR	:	'#TLDR' | '#tldr';
 TEXT 	: 	('A'..'Z' | 'a'..'z' | '0'..'9' | ',' | '.' | '"' | ':' | '?' | '!' | '%' | '/' | ' ' | '\t' | '\r' | '\n')+;
-ADDRESS : 	('A'..'Z' | 'a'..'z' | '0'..'9' | ':' | '/' | '.' | '%' | '_' | '-')+;
+ADDRESS : 	('A'..'Z' | 'a'..'z' | '0'..'9' | ':' | '/' | '.' | '%' | '_' | '-' | '?' | '=' | '&' | '@' | '#')+;
 
 lolcode	:	HAI body KTHXBYE;
-body	:	(head? comment* content*);
-head 	:	 MAEK_HEAD title OIC;
-title 	: 	GIMMEH_TITLE TEXT MKAY;
+body	:	(comment* metadata? (comment | varDefine)* head? comment* content*);
+metadata:	MAEK_INFO metadataEntry+ OIC;
+metadataEntry
+	:	(GIMMEH_AUTHOR | GIMMEH_DATE | GIMMEH_DESCRIPSHUN | GIMMEH_KEYWURDZ | GIMMEH_TAGZ) plainText+ MKAY;
+// At least one title; the semantic pass rejects a second title or icon
+// and repeated stylesheets, scripts and meta names
+head 	:	 MAEK_HEAD headElement+ OIC;
+headElement
+	:	title | stylesheet | style | scriptLink | script | favicon | meta;
+stylesheet
+	:	GIMMEH_STYLESHEET ADDRESS MKAY;
+// Like a code block, the lexer passes the content through as RAW
+style	:	MAEK_STYLE RAW OIC;
+scriptLink
+	:	GIMMEH_SCRIPT ADDRESS MKAY;
+script	:	MAEK_SCRIPT RAW OIC;
+favicon	:	GIMMEH_ICON ADDRESS MKAY;
+meta	:	GIMMEH_META TEXT IT_IZ plainText+ MKAY;
+title 	: 	GIMMEH_TITLE plainText+ MKAY;
 comment : 	OBTW TEXT TLDR;
-content	: 	paragraph 
-	        | bold 
-	        | italics
+content	: 	heading
+	        | toc
+	        | paragraph 
+	        | formatting
 	        | listblock
+	        | table
+	        | codeblock
+	        | quote
+	        | hrule
+	        | link
+	        | image
 	        | newline
 	        | video
 	        | audio
 	        | varDefine
+	        | varAssign
 	        | varUse
+	        | macroDefine
+	        | macroCall
+	        | include
+	        | conditional
+	        | loop
 	        | text
 	        ;
+heading	:	MAEK_HEADR LEVEL inline+ MKAY;
+toc	:	GIMMEH_TOC MKAY;
+LEVEL	:	'1'..'6';
+NUMBER	:	'-'? ('0'..'9')+;
+inline	:	formatting | varUse | link | text;
+formatting
+	:	bold | italics | code | strike | underline | subscript | superscript | highlight;
 paragraph 
 	: MAEK_PARAGRAF paragraphContent* OIC;
-bold 	: GIMMEH_BOLD TEXT MKAY;
-italics : GIMMEH_ITALICS TEXT MKAY;
+quote	:	MAEK_QUOTE paragraphContent* citation? OIC;
+citation:	GIMMEH_CITE inline+ MKAY;
+hrule	:	GIMMEH_LINE;
+bold 	: GIMMEH_BOLD inline+ MKAY;
+italics : GIMMEH_ITALICS inline+ MKAY;
+code	: GIMMEH_KODE inline+ MKAY;
+strike	: GIMMEH_STRIKE inline+ MKAY;
+underline : GIMMEH_UNDERLYNE inline+ MKAY;
+subscript : GIMMEH_SUBSKRIPT inline+ MKAY;
+superscript : GIMMEH_SUPRSKRIPT inline+ MKAY;
+highlight : GIMMEH_HILITE inline+ MKAY;
 listblock
-	:	MAEK_LIST listItem+ OIC;
-listItem:	GIMMEH_ITEM TEXT MKAY;
+	:	(MAEK_LIST | MAEK_NUMBRD_LIST) listEntry+ OIC;
+listItem:	GIMMEH_ITEM itemContent+ MKAY;
+itemContent
+	:	inline | listblock;
+// The lexer passes everything between MAEK_KODEZ and the next OIC through
+// untouched as RAW: an optional language word on the first line, then the
+// code with its whitespace and '#' characters preserved. A \#OIC inside it
+// is a literal #OIC rather than the end of the block
+codeblock
+	:	MAEK_KODEZ RAW OIC;
+table	:	MAEK_TABUL headerRow? row+ OIC;
+headerRow
+	:	GIMMEH_HEDR_ROW cell+ MKAY;
+row	:	GIMMEH_ROW cell+ MKAY;
+cell	:	GIMMEH_CELL inline* MKAY;
 
 newline	:	GIMMEH_NEWLINE;
 video : GIMMEH_VIDZ ADDRESS MKAY;
 audio : GIMMEH_SOUNDZ ADDRESS MKAY;
+link	:	GIMMEH_LINX ADDRESS (IT_IZ plainText+)? MKAY;
+image	:	GIMMEH_PIKCHUR ADDRESS (IT_IZ plainText+)? MKAY;
 paragraphContent
-	:	bold | italics | audio | video | newline | listblock | text;
+	:	formatting | audio | video | link | image | newline | listblock
+	|	varDefine | varAssign | macroCall | varUse | text;
 varDefine
-	:	I_HAZ TEXT IT_IZ TEXT MKAY;
+	:	(I_HAZ | I_ALWAYS_HAZ) TEXT IT_IZ value;
+varAssign
+	:	NAO TEXT R value;
+value	:	(expression | itemContent+) MKAY;
+expression
+	:	(SUM_OF | DIFF_OF | PRODUKT_OF | BOTH_SAEM) operand AN operand
+	|	SMOOSH operand (AN operand)* MKAY;
+operand	:	expression | varUse | TEXT;
 text	:	TEXT;
+plainText
+	:	varUse | text;
 varUse	:	LEMME_SEE TEXT MKAY;
+macroDefine
+	:	HOW_IZ_I TEXT (YR TEXT (AN_YR TEXT)*)? content* IF_U_SAY_SO;
+macroCall
+	:	I_IZ TEXT (YR itemContent+ (AN_YR itemContent+)*)? MKAY;
+include	:	CAN_HAS TEXT MKAY;
+conditional
+	:	O_RLY TEXT (IT_IZ TEXT)? YA_RLY content* (NO_WAI content*)? OIC;
+loop	:	IM_IN_YR TEXT loopSource content* IM_OUTTA_YR;
+loopSource
+	:	OV TEXT | FRUM NUMBER TIL NUMBER;
+listEntry
+	:	listItem | IM_IN_YR TEXT loopSource listEntry* IM_OUTTA_YR;
 
 
 	
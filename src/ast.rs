@@ -0,0 +1,483 @@
+use crate::diagnostic::Span;
+use std::fmt;
+
+/// Abstract syntax tree built by the parser.
+///
+/// The parser only checks structure; it no longer emits HTML itself.
+/// The tree is handed to the SemanticAnalyzer, which resolves variables
+/// and generates the output, and to tools such as the language server
+/// that need to know where each construct lives in the source.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    /// Everything between #HAI and #KTHXBYE, in source order
+    pub body: Vec<Node>,
+    pub span: Span,
+}
+
+/// One construct of the language together with its source location
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    /// #OBTW ... #TLDR
+    Comment(String),
+    /// #MAEK INFO ... #OIC at the top of the program, holding its fields
+    Metadata(Vec<Node>),
+    /// #GIMMEH AUTHOR ... #MKAY and the other fields of #MAEK INFO, the
+    /// value holding text and variable uses
+    MetadataEntry {
+        field: MetadataField,
+        value: Vec<Node>,
+    },
+    /// #MAEK HEAD ... #OIC, holding a title and any other head elements in
+    /// source order
+    Head(Vec<Node>),
+    /// #GIMMEH TITLE ... #MKAY, holding text and variable uses
+    Title(Vec<Node>),
+    /// #GIMMEH STYLESHEET address #MKAY, a linked stylesheet
+    Stylesheet(String),
+    /// #MAEK STYLE ... #OIC, CSS taken verbatim
+    Style(String),
+    /// #GIMMEH SCRIPT address #MKAY, a linked script
+    ScriptLink(String),
+    /// #MAEK SCRIPT ... #OIC, JavaScript taken verbatim
+    Script(String),
+    /// #GIMMEH ICON address #MKAY, the page's favicon
+    Favicon(String),
+    /// #GIMMEH META name #IT IZ content #MKAY, the content holding text and
+    /// variable uses
+    Meta { name: String, content: Vec<Node> },
+    /// #MAEK HEADR level ... #MKAY, level 1 to 6
+    Heading { level: u8, children: Vec<Node> },
+    /// #GIMMEH TOC #MKAY, replaced by links to every heading
+    TableOfContents,
+    /// #MAEK PARAGRAF ... #OIC, opens a new scope
+    Paragraph(Vec<Node>),
+    /// #GIMMEH BOLD ... #MKAY
+    Bold(Vec<Node>),
+    /// #GIMMEH ITALICS ... #MKAY
+    Italics(Vec<Node>),
+    /// #GIMMEH KODE ... #MKAY
+    InlineCode(Vec<Node>),
+    /// #GIMMEH STRIKE ... #MKAY
+    Strikethrough(Vec<Node>),
+    /// #GIMMEH UNDERLYNE ... #MKAY
+    Underline(Vec<Node>),
+    /// #GIMMEH SUBSKRIPT ... #MKAY
+    Subscript(Vec<Node>),
+    /// #GIMMEH SUPRSKRIPT ... #MKAY
+    Superscript(Vec<Node>),
+    /// #GIMMEH HILITE ... #MKAY
+    Highlight(Vec<Node>),
+    /// #MAEK LIST ... #OIC or #MAEK NUMBRD LIST ... #OIC, opens a new scope
+    List { ordered: bool, items: Vec<Node> },
+    /// #GIMMEH ITEM ... #MKAY, holding inline content and nested lists
+    Item(Vec<Node>),
+    /// #GIMMEH LINX address #IT IZ label #MKAY, label optional
+    Link {
+        address: String,
+        label: Option<Vec<Node>>,
+    },
+    /// #GIMMEH PIKCHUR address #IT IZ alt text #MKAY, alt text optional
+    Image {
+        address: String,
+        alt: Option<Vec<Node>>,
+    },
+    /// #MAEK QUOTE ... #GIMMEH CITE ... #MKAY #OIC, citation optional;
+    /// opens a new scope like a paragraph
+    Quote {
+        children: Vec<Node>,
+        citation: Option<Vec<Node>>,
+    },
+    /// #GIMMEH LINE
+    HorizontalRule,
+    /// #MAEK KODEZ language ... #OIC, content kept verbatim
+    CodeBlock {
+        language: Option<String>,
+        code: String,
+    },
+    /// #MAEK TABUL ... #OIC
+    Table(Vec<Node>),
+    /// #GIMMEH ROW ... #MKAY, or #GIMMEH HEDR ROW ... #MKAY as a table's first row
+    Row { header: bool, cells: Vec<Node> },
+    /// #GIMMEH CELL ... #MKAY
+    Cell(Vec<Node>),
+    /// #GIMMEH NEWLINE
+    Newline,
+    /// #GIMMEH SOUNDZ address #MKAY
+    Audio(String),
+    /// #GIMMEH VIDZ address #MKAY
+    Video(String),
+    /// #I HAZ name #IT IZ value #MKAY, the value being inline content and
+    /// lists or an expression. #I ALWAYS HAZ defines a constant.
+    VarDefine {
+        name: String,
+        name_span: Span,
+        value: Vec<Node>,
+        constant: bool,
+    },
+    /// #NAO name #R value #MKAY, changing the closest visible definition
+    VarAssign {
+        name: String,
+        name_span: Span,
+        value: Vec<Node>,
+    },
+    /// #LEMME SEE name #MKAY
+    VarUse { name: String, name_span: Span },
+    /// #HOW IZ I name #YR param #AN YR param ... #IF U SAY SO, parameters
+    /// optional
+    MacroDefine {
+        name: String,
+        name_span: Span,
+        params: Vec<(String, Span)>,
+        body: Vec<Node>,
+    },
+    /// #O RLY name #IT IZ value #YA RLY ... #NO WAI ... #OIC, the value
+    /// and the #NO WAI branch optional. Without a value the condition is
+    /// whether the variable is defined.
+    Conditional {
+        name: String,
+        name_span: Span,
+        value: Option<String>,
+        branches: Vec<Node>,
+    },
+    /// The #YA RLY or #NO WAI content of a conditional
+    Branch(Vec<Node>),
+    /// #IM IN YR variable #OV list ... #IM OUTTA YR or
+    /// #IM IN YR variable #FRUM start #TIL end ... #IM OUTTA YR
+    Loop {
+        variable: String,
+        variable_span: Span,
+        source: LoopSource,
+        body: Vec<Node>,
+    },
+    /// An expression in a variable value, e.g. #SUM OF a #AN b. Operands
+    /// are literal text, variable uses and further operations.
+    Operation {
+        operator: Operator,
+        operands: Vec<Node>,
+    },
+    /// #CAN HAS file #MKAY, replaced by the file's content before the
+    /// semantic pass
+    Include(String),
+    /// #I IZ name #YR argument #AN YR argument ... #MKAY, arguments optional
+    MacroCall {
+        name: String,
+        name_span: Span,
+        args: Vec<Vec<Node>>,
+    },
+    /// Plain text between keywords
+    Text(String),
+}
+
+/// Operators of variable value expressions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    /// #SUM OF a #AN b
+    Sum,
+    /// #DIFF OF a #AN b
+    Diff,
+    /// #PRODUKT OF a #AN b
+    Produkt,
+    /// #SMOOSH a #AN b ... #MKAY, concatenating any number of operands
+    Smoosh,
+    /// #BOTH SAEM a #AN b, equality giving a TROOF
+    BothSaem,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keyword = match self {
+            Operator::Sum => "SUM OF",
+            Operator::Diff => "DIFF OF",
+            Operator::Produkt => "PRODUKT OF",
+            Operator::Smoosh => "SMOOSH",
+            Operator::BothSaem => "BOTH SAEM",
+        };
+        write!(f, "{}", keyword)
+    }
+}
+
+/// Fields of the #MAEK INFO section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataField {
+    /// #GIMMEH AUTHOR
+    Author,
+    /// #GIMMEH DATE
+    Date,
+    /// #GIMMEH DESCRIPSHUN
+    Description,
+    /// #GIMMEH KEYWURDZ, comma-separated
+    Keywords,
+    /// #GIMMEH TAGZ, comma-separated
+    Tags,
+}
+
+impl MetadataField {
+    /// Name of the field's <meta> tag
+    pub fn name(self) -> &'static str {
+        match self {
+            MetadataField::Author => "author",
+            MetadataField::Date => "date",
+            MetadataField::Description => "description",
+            MetadataField::Keywords => "keywords",
+            MetadataField::Tags => "tags",
+        }
+    }
+
+    /// Name of the constant holding the field, e.g. `info.author`, kept
+    /// apart from the document's own variables
+    pub fn variable(self) -> String {
+        format!("info.{}", self.name())
+    }
+}
+
+/// What a loop runs over
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoopSource {
+    /// The comma-separated words of a variable's value
+    Variable { name: String, name_span: Span },
+    /// Every whole number from `from` to `to`, both included, counting
+    /// down if `to` is smaller
+    Range { from: i64, to: i64 },
+}
+
+impl Node {
+    pub fn new(kind: NodeKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Indented one-line-per-node outline of this subtree, for debugging
+    pub fn write_tree(&self, out: &mut String, depth: usize) {
+        let label = match &self.kind {
+            NodeKind::Comment(text) => format!("Comment {:?}", text),
+            NodeKind::Metadata(_) => "Metadata".to_string(),
+            NodeKind::MetadataEntry { field, .. } => format!("MetadataEntry {}", field.name()),
+            NodeKind::Head(_) => "Head".to_string(),
+            NodeKind::Title(_) => "Title".to_string(),
+            NodeKind::Stylesheet(address) => format!("Stylesheet {}", address),
+            NodeKind::Style(_) => "Style".to_string(),
+            NodeKind::ScriptLink(address) => format!("ScriptLink {}", address),
+            NodeKind::Script(_) => "Script".to_string(),
+            NodeKind::Favicon(address) => format!("Favicon {}", address),
+            NodeKind::Meta { name, .. } => format!("Meta {}", name),
+            NodeKind::Heading { level, .. } => format!("Heading {}", level),
+            NodeKind::TableOfContents => "TableOfContents".to_string(),
+            NodeKind::Paragraph(_) => "Paragraph".to_string(),
+            NodeKind::Bold(_) => "Bold".to_string(),
+            NodeKind::Italics(_) => "Italics".to_string(),
+            NodeKind::InlineCode(_) => "InlineCode".to_string(),
+            NodeKind::Strikethrough(_) => "Strikethrough".to_string(),
+            NodeKind::Underline(_) => "Underline".to_string(),
+            NodeKind::Subscript(_) => "Subscript".to_string(),
+            NodeKind::Superscript(_) => "Superscript".to_string(),
+            NodeKind::Highlight(_) => "Highlight".to_string(),
+            NodeKind::List { ordered: false, .. } => "List".to_string(),
+            NodeKind::List { ordered: true, .. } => "List numbered".to_string(),
+            NodeKind::Item(_) => "Item".to_string(),
+            NodeKind::Link { address, .. } => format!("Link {:?}", address),
+            NodeKind::Image { address, .. } => format!("Image {:?}", address),
+            NodeKind::Quote { citation: None, .. } => "Quote".to_string(),
+            NodeKind::Quote { citation: Some(_), .. } => "Quote cited".to_string(),
+            NodeKind::HorizontalRule => "HorizontalRule".to_string(),
+            NodeKind::CodeBlock { language, code } => {
+                format!("CodeBlock {:?} {:?}", language, code)
+            }
+            NodeKind::Table(_) => "Table".to_string(),
+            NodeKind::Row { header: false, .. } => "Row".to_string(),
+            NodeKind::Row { header: true, .. } => "Row header".to_string(),
+            NodeKind::Cell(_) => "Cell".to_string(),
+            NodeKind::Newline => "Newline".to_string(),
+            NodeKind::Audio(address) => format!("Audio {:?}", address),
+            NodeKind::Video(address) => format!("Video {:?}", address),
+            NodeKind::VarDefine {
+                name,
+                constant: false,
+                ..
+            } => format!("VarDefine {}", name),
+            NodeKind::VarDefine {
+                name,
+                constant: true,
+                ..
+            } => format!("VarDefine constant {}", name),
+            NodeKind::VarAssign { name, .. } => format!("VarAssign {}", name),
+            NodeKind::VarUse { name, .. } => format!("VarUse {}", name),
+            NodeKind::MacroDefine { name, params, .. } => {
+                let params: Vec<&str> = params.iter().map(|(param, _)| param.as_str()).collect();
+                format!("MacroDefine {}({})", name, params.join(", "))
+            }
+            NodeKind::Include(path) => format!("Include {:?}", path),
+            NodeKind::Conditional { name, value, .. } => match value {
+                Some(value) => format!("Conditional {} == {:?}", name, value),
+                None => format!("Conditional {} defined", name),
+            },
+            NodeKind::Branch(_) => "Branch".to_string(),
+            NodeKind::Operation { operator, .. } => format!("Operation {}", operator),
+            NodeKind::Loop {
+                variable, source, ..
+            } => match source {
+                LoopSource::Variable { name, .. } => format!("Loop {} over {}", variable, name),
+                LoopSource::Range { from, to } => format!("Loop {} from {} to {}", variable, from, to),
+            },
+            NodeKind::MacroCall { name, args, .. } => {
+                format!("MacroCall {} with {} argument(s)", name, args.len())
+            }
+            NodeKind::Text(text) => format!("Text {:?}", text),
+        };
+        out.push_str(&format!(
+            "{}{} @{}..{}\n",
+            "  ".repeat(depth),
+            label,
+            self.span.start,
+            self.span.end
+        ));
+        for child in self.child_lists().into_iter().flatten() {
+            child.write_tree(out, depth + 1);
+        }
+    }
+
+    /// Nested nodes of block constructs and the text of titles, labels and
+    /// alt text; empty for everything else
+    pub fn children(&self) -> &[Node] {
+        match &self.kind {
+            NodeKind::Paragraph(children)
+            | NodeKind::List {
+                items: children, ..
+            }
+            | NodeKind::Item(children)
+            | NodeKind::Heading { children, .. }
+            | NodeKind::Table(children)
+            | NodeKind::Row {
+                cells: children, ..
+            }
+            | NodeKind::Cell(children)
+            | NodeKind::Bold(children)
+            | NodeKind::Italics(children)
+            | NodeKind::InlineCode(children)
+            | NodeKind::Strikethrough(children)
+            | NodeKind::Underline(children)
+            | NodeKind::Subscript(children)
+            | NodeKind::Superscript(children)
+            | NodeKind::Highlight(children)
+            | NodeKind::Quote { children, .. }
+            | NodeKind::Head(children)
+            | NodeKind::Title(children)
+            | NodeKind::Meta {
+                content: children, ..
+            }
+            | NodeKind::Metadata(children)
+            | NodeKind::MetadataEntry {
+                value: children, ..
+            }
+            | NodeKind::VarDefine {
+                value: children, ..
+            }
+            | NodeKind::VarAssign {
+                value: children, ..
+            }
+            | NodeKind::MacroDefine { body: children, .. }
+            | NodeKind::Conditional {
+                branches: children,
+                ..
+            }
+            | NodeKind::Branch(children)
+            | NodeKind::Loop { body: children, .. }
+            | NodeKind::Operation {
+                operands: children,
+                ..
+            }
+            | NodeKind::Link {
+                label: Some(children),
+                ..
+            }
+            | NodeKind::Image {
+                alt: Some(children),
+                ..
+            } => children,
+            _ => &[],
+        }
+    }
+
+    /// Every list of nested nodes: `children`, plus a quote's citation and
+    /// each argument of a macro call
+    pub fn child_lists(&self) -> Vec<&[Node]> {
+        match &self.kind {
+            NodeKind::Quote {
+                children,
+                citation: Some(citation),
+            } => vec![children, citation],
+            NodeKind::MacroCall { args, .. } => args.iter().map(Vec::as_slice).collect(),
+            _ => vec![self.children()],
+        }
+    }
+
+    /// Mutable counterpart of `child_lists`
+    pub fn child_lists_mut(&mut self) -> Vec<&mut Vec<Node>> {
+        match &mut self.kind {
+            NodeKind::Quote {
+                children,
+                citation: Some(citation),
+            } => vec![children, citation],
+            NodeKind::MacroCall { args, .. } => args.iter_mut().collect(),
+            NodeKind::Paragraph(children)
+            | NodeKind::Bold(children)
+            | NodeKind::Italics(children)
+            | NodeKind::InlineCode(children)
+            | NodeKind::Strikethrough(children)
+            | NodeKind::Underline(children)
+            | NodeKind::Subscript(children)
+            | NodeKind::Superscript(children)
+            | NodeKind::Highlight(children)
+            | NodeKind::List {
+                items: children, ..
+            }
+            | NodeKind::Item(children)
+            | NodeKind::Heading { children, .. }
+            | NodeKind::Table(children)
+            | NodeKind::Row {
+                cells: children, ..
+            }
+            | NodeKind::Cell(children)
+            | NodeKind::Quote { children, .. }
+            | NodeKind::Head(children)
+            | NodeKind::Title(children)
+            | NodeKind::Meta {
+                content: children, ..
+            }
+            | NodeKind::Metadata(children)
+            | NodeKind::MetadataEntry {
+                value: children, ..
+            }
+            | NodeKind::VarDefine {
+                value: children, ..
+            }
+            | NodeKind::VarAssign {
+                value: children, ..
+            }
+            | NodeKind::MacroDefine { body: children, .. }
+            | NodeKind::Conditional {
+                branches: children,
+                ..
+            }
+            | NodeKind::Branch(children)
+            | NodeKind::Loop { body: children, .. }
+            | NodeKind::Operation {
+                operands: children,
+                ..
+            }
+            | NodeKind::Link {
+                label: Some(children),
+                ..
+            }
+            | NodeKind::Image {
+                alt: Some(children),
+                ..
+            } => vec![children],
+            _ => Vec::new(),
+        }
+    }
+}
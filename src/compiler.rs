@@ -1,7 +1,13 @@
+use crate::ast::Document;
+use crate::diagnostic::{Diagnostic, LineIndex};
+use crate::include::Includer;
 use crate::lexer::Lexer;
+use crate::output::{self, OutputStyle};
 use crate::parser::{Parser, SyntaxAnalyzer};
-use crate::semantic::SemanticAnalyzer;
+use crate::semantic::{CLASS_ELEMENTS, Metadata, SemanticAnalyzer, Stylesheet, check_address};
+use crate::theme;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{Command, exit};
 
 /// Compiler trait as specified in project requirements
@@ -19,6 +25,12 @@ pub struct LolCompiler {
     #[allow(dead_code)]
     pub current_token: String,
     semantic: SemanticAnalyzer,
+    /// Errors and warnings from the last compilation, in phase order
+    diagnostics: Vec<Diagnostic>,
+    /// File being compiled, which included files are relative to
+    source_file: Option<PathBuf>,
+    /// Layout of the HTML written by `compile_file`
+    output_style: OutputStyle,
 }
 
 impl LolCompiler {
@@ -26,16 +38,129 @@ impl LolCompiler {
         Self {
             current_token: String::new(),
             semantic: SemanticAnalyzer::new(),
+            diagnostics: Vec::new(),
+            source_file: None,
+            output_style: OutputStyle::AsIs,
         }
     }
 
-    pub fn compile_file(&mut self, source: &str, input_file: &str) {
+    /// Compile as the file at `path`: included files and local images are
+    /// looked up next to it
+    pub fn set_source_file(&mut self, path: &Path) {
+        if let Some(dir) = path.parent() {
+            self.semantic.set_source_dir(dir.to_path_buf());
+        }
+        self.source_file = Some(path.to_path_buf());
+    }
+
+    /// Give the document a variable without editing its source; see
+    /// `SemanticAnalyzer::predefine` for how it combines with definitions
+    /// in the file
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.semantic.predefine(name.to_string(), value);
+    }
+
+    /// Emit #GIMMEH SCRIPT and #MAEK SCRIPT instead of reporting them
+    pub fn allow_scripts(&mut self, allow: bool) {
+        self.semantic.set_allow_scripts(allow);
+    }
+
+    /// Lay out the HTML written by `compile_file` in `style`
+    pub fn set_output_style(&mut self, style: OutputStyle) {
+        self.output_style = style;
+    }
+
+    /// Style the output with the built-in theme `name`
+    pub fn set_theme(&mut self, name: &str) -> Result<(), String> {
+        let css = theme::stylesheet(name).ok_or_else(|| {
+            format!("Unknown theme '{}'; themes are {}", name, theme::names().join(", "))
+        })?;
+        self.semantic.set_theme(css);
+        Ok(())
+    }
+
+    /// Add a stylesheet after the document's own, so it overrides them.
+    /// A linked one's address is checked like a #GIMMEH STYLESHEET's.
+    pub fn add_stylesheet(&mut self, stylesheet: Stylesheet) -> Result<(), String> {
+        if let Stylesheet::Link(address) = &stylesheet {
+            check_address(address)
+                .map_err(|reason| format!("Invalid stylesheet address '{}': {}", address, reason))?;
+        }
+        self.semantic.add_stylesheet(stylesheet);
+        Ok(())
+    }
+
+    /// Give every emitted `tag` element the class(es) `class`
+    pub fn set_class(&mut self, tag: &str, class: &str) -> Result<(), String> {
+        if !CLASS_ELEMENTS.contains(&tag) {
+            return Err(format!(
+                "Cannot set a class on '{}'; elements are {}",
+                tag,
+                CLASS_ELEMENTS.join(", ")
+            ));
+        }
+        let valid = |name: &str| name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if class.split_whitespace().next().is_none() || !class.split_whitespace().all(valid) {
+            return Err(format!("'{}' is not a valid class name", class));
+        }
+        let class = class.split_whitespace().collect::<Vec<_>>().join(" ");
+        self.semantic.set_class(tag, &class);
+        Ok(())
+    }
+
+    /// Run the lexer, parser and SemanticAnalyzer over `source` without
+    /// writing anything. Returns the parse tree when the source is
+    /// syntactically valid; problems from every phase end up in
+    /// `diagnostics()`.
+    pub fn analyze(&mut self, source: &str) -> Option<Document> {
+        self.semantic.clear();
+        self.diagnostics.clear();
+
         let mut lexer = Lexer::new(source);
-        let mut parser = Parser::new(&mut lexer, &mut self.semantic);
-        
-        parser.parse_lolcode();
-        
-        let html_output = self.semantic.get_output();
+        let mut parser = Parser::new(&mut lexer);
+
+        match parser.parse_lolcode() {
+            Ok(mut document) => {
+                let mut includer = Includer::new(self.source_file.as_deref());
+                includer.resolve(&mut document.body);
+                self.diagnostics.extend_from_slice(includer.diagnostics());
+
+                self.semantic.analyze(&document);
+                self.diagnostics
+                    .extend_from_slice(self.semantic.diagnostics());
+                Some(document)
+            }
+            Err(error) => {
+                self.diagnostics.push(error);
+                None
+            }
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The #MAEK INFO section of the last analyzed source, with variable
+    /// uses resolved
+    pub fn metadata(&self) -> &Metadata {
+        self.semantic.metadata()
+    }
+
+    pub fn semantic(&self) -> &SemanticAnalyzer {
+        &self.semantic
+    }
+
+    pub fn compile_file(&mut self, source: &str, input_file: &str) {
+        self.set_source_file(Path::new(input_file));
+        self.analyze(source);
+        self.report(source, input_file);
+
+        if self.diagnostics.iter().any(Diagnostic::is_error) {
+            exit(1);
+        }
+
+        let html_output = output::restyle(self.semantic.get_output(), self.output_style);
         let output_file = input_file.replace(".lol", ".html");
         
         if let Err(e) = fs::write(&output_file, html_output) {
@@ -46,6 +171,15 @@ impl LolCompiler {
         self.launch_browser(&output_file);
     }
 
+    /// Print every diagnostic as `file:line:column: message`
+    fn report(&self, source: &str, input_file: &str) {
+        let lines = LineIndex::new(source);
+        for diagnostic in &self.diagnostics {
+            let (line, column) = lines.line_col(diagnostic.span.start);
+            eprintln!("{}:{}:{}: {}", input_file, line + 1, column + 1, diagnostic);
+        }
+    }
+
     fn launch_browser(&self, html_file: &str) {
         let abs_path = std::env::current_dir()
             .unwrap()
@@ -77,11 +211,15 @@ impl LolCompiler {
     }
 }
 
+impl Default for LolCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Compiler for LolCompiler {
     fn compile(&mut self, source: &str) {
-        let mut lexer = Lexer::new(source);
-        let mut parser = Parser::new(&mut lexer, &mut self.semantic);
-        parser.parse_lolcode();
+        self.analyze(source);
     }
 
     fn next_token(&mut self) -> String {
@@ -100,3 +238,32 @@ impl Compiler for LolCompiler {
         self.current_token = tok;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposes_metadata_and_keeps_its_variables_apart() {
+        let mut compiler = LolCompiler::new();
+        compiler.analyze(
+            "#HAI\n\
+             #MAEK INFO\n\
+             #GIMMEH AUTHOR Lisa #MKAY\n\
+             #GIMMEH DATE 1990-03-11 #MKAY\n\
+             #GIMMEH KEYWURDZ beer, donuts #MKAY\n\
+             #OIC\n\
+             #I HAZ date #IT IZ Friday #MKAY\n\
+             #MAEK PARAGRAF #LEMME SEE info.date #MKAY #OIC\n\
+             #MAEK PARAGRAF #LEMME SEE date #MKAY #OIC\n\
+             #KTHXBYE",
+        );
+        assert!(compiler.diagnostics().is_empty(), "{:?}", compiler.diagnostics());
+        let metadata = compiler.metadata();
+        assert_eq!(metadata.author.as_deref(), Some("Lisa"));
+        assert_eq!(metadata.date.as_deref(), Some("1990-03-11"));
+        assert_eq!(metadata.keywords, ["beer", "donuts"]);
+        assert!(metadata.tags.is_empty());
+        assert!(compiler.semantic().get_output().contains("<p>1990-03-11</p>\n<p>Friday</p>"));
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+
+/// A region of the source file, measured in characters from the start.
+///
+/// Spans are attached to every token and AST node so that errors can point
+/// at the exact place in the .lol file, and so the language server can map
+/// editor positions back to the parse tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// True if the character offset lies inside the span (end inclusive,
+    /// so a cursor placed right after a word still counts as on it)
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+}
+
+/// Which compiler phase reported the problem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Lexical,
+    Syntax,
+    Semantic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single error or warning produced while compiling
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub phase: Phase,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn lexical(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Phase::Lexical, Severity::Error, message, span)
+    }
+
+    pub fn syntax(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Phase::Syntax, Severity::Error, message, span)
+    }
+
+    pub fn semantic(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Phase::Semantic, Severity::Error, message, span)
+    }
+
+    /// Something legal but probably wrong, reported by the semantic pass.
+    /// Warnings never stop compilation.
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Phase::Semantic, Severity::Warning, message, span)
+    }
+
+    fn new(phase: Phase, severity: Severity, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            phase,
+            severity,
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match (self.severity, self.phase) {
+            (Severity::Warning, _) => "Warning",
+            (Severity::Error, Phase::Lexical) => "Lexical error",
+            (Severity::Error, Phase::Syntax) => "Syntax error",
+            (Severity::Error, Phase::Semantic) => "Static semantic error",
+        };
+        write!(f, "{}: {}", label, self.message)
+    }
+}
+
+/// Maps character offsets to 0-based (line, column) pairs.
+pub struct LineIndex {
+    chars: Vec<char>,
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let chars: Vec<char> = source.chars().collect();
+        let mut line_starts = vec![0];
+        for (i, c) in chars.iter().enumerate() {
+            if *c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        Self { chars, line_starts }
+    }
+
+    /// Line and column of a character offset, both counted in characters
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        (line, offset - self.line_starts[line])
+    }
+
+    /// Line and column of a character offset, with the column counted in
+    /// UTF-16 code units as editors speaking LSP expect
+    pub fn line_col_utf16(&self, offset: usize) -> (usize, usize) {
+        let (line, _) = self.line_col(offset);
+        let start = self.line_starts[line];
+        let end = offset.min(self.chars.len());
+        let column = self.chars[start..end].iter().map(|c| c.len_utf16()).sum();
+        (line, column)
+    }
+
+    /// Inverse of `line_col_utf16`, clamping to the end of the line
+    pub fn offset_utf16(&self, line: usize, column: usize) -> usize {
+        let Some(&start) = self.line_starts.get(line) else {
+            return self.chars.len();
+        };
+        let mut offset = start;
+        let mut units = 0;
+        while offset < self.chars.len() && self.chars[offset] != '\n' && units < column {
+            units += self.chars[offset].len_utf16();
+            offset += 1;
+        }
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineIndex;
+
+    #[test]
+    fn counts_columns_in_characters_and_utf16_units() {
+        // 'é' is one UTF-16 unit, '😀' is two
+        let lines = LineIndex::new("ab\né😀x\n");
+        assert_eq!(lines.line_col(1), (0, 1));
+        assert_eq!(lines.line_col(3), (1, 0));
+        assert_eq!(lines.line_col(5), (1, 2));
+        assert_eq!(lines.line_col_utf16(5), (1, 3));
+        assert_eq!(lines.line_col_utf16(6), (1, 4));
+    }
+
+    #[test]
+    fn converts_utf16_positions_back_to_offsets() {
+        let lines = LineIndex::new("ab\né😀x\n");
+        for offset in 0..=7 {
+            let (line, column) = lines.line_col_utf16(offset);
+            assert_eq!(lines.offset_utf16(line, column), offset);
+        }
+        // Past the end of a line or of the text is clamped
+        assert_eq!(lines.offset_utf16(0, 10), 2);
+        assert_eq!(lines.offset_utf16(9, 0), 7);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::ast::Operator;
+use std::fmt;
+
+/// A typed value computed by an expression at compile time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Whole number
+    Numbr(i64),
+    /// Floating point number
+    Numbar(f64),
+    /// String
+    Yarn(String),
+    /// Boolean, written WIN or FAIL
+    Troof(bool),
+}
+
+impl Value {
+    /// Type of a literal or of a variable's text: a whole number is a
+    /// NUMBR, a decimal number a NUMBAR, WIN and FAIL are TROOFs and
+    /// everything else is a YARN
+    pub fn from_text(text: &str) -> Self {
+        let text = text.trim();
+        if let Ok(number) = text.parse::<i64>() {
+            Value::Numbr(number)
+        } else if let Some(number) = text.parse::<f64>().ok().filter(|_| looks_numeric(text)) {
+            Value::Numbar(number)
+        } else if text == "WIN" {
+            Value::Troof(true)
+        } else if text == "FAIL" {
+            Value::Troof(false)
+        } else {
+            Value::Yarn(text.to_string())
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Numbr(_) => "NUMBR",
+            Value::Numbar(_) => "NUMBAR",
+            Value::Yarn(_) => "YARN",
+            Value::Troof(_) => "TROOF",
+        }
+    }
+
+    fn as_numbar(&self) -> Option<f64> {
+        match self {
+            Value::Numbr(number) => Some(*number as f64),
+            Value::Numbar(number) => Some(*number),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Numbr(number) => write!(f, "{}", number),
+            // Two decimals, as LOLCODE prints NUMBARs
+            Value::Numbar(number) => write!(f, "{:.2}", number),
+            Value::Yarn(text) => write!(f, "{}", text),
+            Value::Troof(true) => write!(f, "WIN"),
+            Value::Troof(false) => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// An evaluated operand together with how it is written: literals and
+/// variables as in the source, results of operations as displayed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operand {
+    pub value: Value,
+    pub text: String,
+}
+
+impl Operand {
+    /// Typed the way `Value::from_text` does, keeping the text itself
+    pub fn from_text(text: &str) -> Self {
+        Self {
+            value: Value::from_text(text),
+            text: text.trim().to_string(),
+        }
+    }
+}
+
+impl From<Value> for Operand {
+    fn from(value: Value) -> Self {
+        Self {
+            text: value.to_string(),
+            value,
+        }
+    }
+}
+
+/// Why an operation failed, and which operand is at fault if it is one
+/// operand's type
+pub struct TypeError {
+    pub operand: Option<usize>,
+    pub message: String,
+}
+
+/// Apply `operator` to already evaluated operands. SMOOSH joins them as
+/// they are written, so `0010` or `2.0` are not reformatted as numbers.
+pub fn apply(operator: Operator, operands: &[Operand]) -> Result<Value, TypeError> {
+    match operator {
+        Operator::Sum | Operator::Diff | Operator::Produkt => {
+            arithmetic(operator, &operands[0].value, &operands[1].value)
+        }
+        Operator::Smoosh => Ok(Value::Yarn(
+            operands.iter().map(|operand| operand.text.as_str()).collect::<String>(),
+        )),
+        Operator::BothSaem => Ok(Value::Troof(match (&operands[0].value, &operands[1].value) {
+            (left, right) if left.as_numbar().is_some() && right.as_numbar().is_some() => {
+                left.as_numbar() == right.as_numbar()
+            }
+            (left, right) => left == right,
+        })),
+    }
+}
+
+/// NUMBR with NUMBR stays a NUMBR; a NUMBAR on either side makes a NUMBAR
+fn arithmetic(operator: Operator, left: &Value, right: &Value) -> Result<Value, TypeError> {
+    for (operand, value) in [left, right].into_iter().enumerate() {
+        if value.as_numbar().is_none() {
+            return Err(TypeError {
+                operand: Some(operand),
+                message: format!(
+                    "{} needs NUMBR or NUMBAR operands, found {} '{}'",
+                    operator, value.type_name(), value
+                ),
+            });
+        }
+    }
+
+    if let (Value::Numbr(left), Value::Numbr(right)) = (left, right) {
+        let result = match operator {
+            Operator::Sum => left.checked_add(*right),
+            Operator::Diff => left.checked_sub(*right),
+            _ => left.checked_mul(*right),
+        };
+        return result.map(Value::Numbr).ok_or(TypeError {
+            operand: None,
+            message: format!("{} {} and {} does not fit in a NUMBR", operator, left, right),
+        });
+    }
+
+    let (left, right) = (left.as_numbar().unwrap(), right.as_numbar().unwrap());
+    Ok(Value::Numbar(match operator {
+        Operator::Sum => left + right,
+        Operator::Diff => left - right,
+        _ => left * right,
+    }))
+}
+
+/// Digits with an optional sign and decimal point, so that words like
+/// "inf" or "NaN" stay YARNs
+fn looks_numeric(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.chars().any(|c| c.is_ascii_digit())
+}
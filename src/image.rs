@@ -0,0 +1,66 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Read the pixel size of a local PNG or JPEG image from its header.
+///
+/// Only the first few kilobytes are read. Returns None for other formats,
+/// unreadable files or headers this simple reader doesn't understand;
+/// the image is then emitted without width/height.
+pub fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(64 * 1024)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    png_dimensions(&header).or_else(|| jpeg_dimensions(&header))
+}
+
+/// PNG: 8-byte signature, then the IHDR chunk holding width and height
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    const SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    if data.len() < 24 || !data.starts_with(SIGNATURE) || &data[12..16] != b"IHDR" {
+        return None;
+    }
+    Some((read_u32(&data[16..20]), read_u32(&data[20..24])))
+}
+
+/// JPEG: walk the marker segments until a start-of-frame marker, whose
+/// payload holds the height and width
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xff {
+            return None;
+        }
+        let marker = data[i + 1];
+        // Padding bytes and markers without a length field
+        if marker == 0xff || marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            i += if marker == 0xff { 1 } else { 2 };
+            continue;
+        }
+        let length = read_u16(&data[i + 2..i + 4]) as usize;
+        let is_frame = matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+        if is_frame {
+            let frame = data.get(i + 4..i + 9)?;
+            let height = read_u16(&frame[1..3]) as u32;
+            let width = read_u16(&frame[3..5]) as u32;
+            return Some((width, height));
+        }
+        i += 2 + length;
+    }
+    None
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
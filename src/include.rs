@@ -0,0 +1,242 @@
+use crate::ast::{LoopSource, Node, NodeKind};
+use crate::diagnostic::{Diagnostic, LineIndex, Span};
+use crate::lexer::Lexer;
+use crate::parser::{Parser, SyntaxAnalyzer};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Replaces every #CAN HAS file #MKAY with the content of that file.
+///
+/// Included files are lexed and parsed on their own, either as a whole
+/// program (#HAI ... #KTHXBYE) or as loose body content, and their nodes
+/// are spliced in where the directive was. Global definitions of an
+/// included file therefore end up in the includer's global scope. Paths
+/// are relative to the file containing the directive.
+///
+/// Spliced nodes take the span of their directive, since their own spans
+/// point into another file. Problems are reported at the directive too.
+/// The #MAEK HEAD and #MAEK INFO of an included program belong to the
+/// compiled file's, so they are left out with a warning.
+pub struct Includer {
+    /// Files being included, outermost first, as written and as found on
+    /// disk; a file that is already on the chain would include itself
+    chain: Vec<(PathBuf, PathBuf)>,
+    /// Span of the directive in the compiled file while its content is
+    /// being resolved; everything inside it is reported there
+    outer_span: Option<Span>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Includer {
+    /// `file` is the file being compiled, if it exists on disk
+    pub fn new(file: Option<&Path>) -> Self {
+        let chain = file
+            .map(|file| vec![(file.to_path_buf(), canonical(file))])
+            .unwrap_or_default();
+        Self {
+            chain,
+            outer_span: None,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Resolve the includes in `nodes` and everything nested in them
+    pub fn resolve(&mut self, nodes: &mut Vec<Node>) {
+        let mut resolved = Vec::with_capacity(nodes.len());
+        for mut node in nodes.drain(..) {
+            if let NodeKind::Include(path) = &node.kind {
+                match self.include(path, node.span) {
+                    Some(included) => resolved.extend(included),
+                    None => resolved.push(node),
+                }
+                continue;
+            }
+            for children in node.child_lists_mut() {
+                self.resolve(children);
+            }
+            resolved.push(node);
+        }
+        *nodes = resolved;
+    }
+
+    /// Parse the file named by a directive at `span`, with its own includes
+    /// resolved. None if it cannot be included.
+    fn include(&mut self, path: &str, span: Span) -> Option<Vec<Node>> {
+        let span = self.outer_span.unwrap_or(span);
+        let dir = match self.chain.last() {
+            Some((file, _)) => file.parent().unwrap_or(Path::new("")).to_path_buf(),
+            None => PathBuf::new(),
+        };
+        let file = dir.join(path);
+        let key = canonical(&file);
+
+        if self.chain.iter().any(|(_, included)| *included == key) {
+            let mut names: Vec<String> = self
+                .chain
+                .iter()
+                .map(|(file, _)| file.display().to_string())
+                .collect();
+            names.push(file.display().to_string());
+            self.diagnostics.push(Diagnostic::semantic(
+                format!("Include cycle: {}", names.join(" -> ")),
+                span,
+            ));
+            return None;
+        }
+
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(error) => {
+                self.diagnostics.push(Diagnostic::semantic(
+                    format!("Cannot read included file '{}': {}", file.display(), error),
+                    span,
+                ));
+                return None;
+            }
+        };
+
+        let mut nodes = match parse(&source) {
+            Ok(nodes) => nodes,
+            Err(error) => {
+                let (line, column) = LineIndex::new(&source).line_col(error.span.start);
+                self.diagnostics.push(Diagnostic {
+                    message: format!(
+                        "{} (in included file '{}', line {}, column {})",
+                        error.message,
+                        file.display(),
+                        line + 1,
+                        column + 1
+                    ),
+                    span,
+                    ..error
+                });
+                return None;
+            }
+        };
+
+        nodes.retain(|node| {
+            let section = match node.kind {
+                NodeKind::Head(_) => "#MAEK HEAD",
+                NodeKind::Metadata(_) => "#MAEK INFO",
+                _ => return true,
+            };
+            self.diagnostics.push(Diagnostic::warning(
+                format!("{} of included file '{}' is ignored", section, file.display()),
+                span,
+            ));
+            false
+        });
+
+        let outer_span = self.outer_span.replace(span);
+        self.chain.push((file, key));
+        self.resolve(&mut nodes);
+        self.chain.pop();
+        self.outer_span = outer_span;
+
+        for node in &mut nodes {
+            relocate(node, span);
+        }
+        Some(nodes)
+    }
+}
+
+/// A whole program or loose body content
+fn parse(source: &str) -> Result<Vec<Node>, Diagnostic> {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    if source.trim_start().to_lowercase().starts_with("#hai") {
+        parser.parse_lolcode().map(|document| document.body)
+    } else {
+        parser.parse_fragment()
+    }
+}
+
+/// Where a file really is, so different spellings of one path compare equal
+fn canonical(file: &Path) -> PathBuf {
+    fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf())
+}
+
+/// Move `node` and everything in it to `span`
+fn relocate(node: &mut Node, span: Span) {
+    node.span = span;
+    match &mut node.kind {
+        NodeKind::VarDefine { name_span, .. }
+        | NodeKind::VarAssign { name_span, .. }
+        | NodeKind::VarUse { name_span, .. } => {
+            *name_span = span
+        }
+        NodeKind::MacroDefine {
+            name_span, params, ..
+        } => {
+            *name_span = span;
+            for (_, param_span) in params {
+                *param_span = span;
+            }
+        }
+        NodeKind::Loop {
+            variable_span,
+            source,
+            ..
+        } => {
+            *variable_span = span;
+            if let LoopSource::Variable { name_span, .. } = source {
+                *name_span = span;
+            }
+        }
+        NodeKind::MacroCall { name_span, .. } => *name_span = span,
+        _ => {}
+    }
+    for child in node.child_lists_mut().into_iter().flatten() {
+        relocate(child, span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spans of `nodes` and everything nested in them
+    fn spans(nodes: &[Node], out: &mut Vec<Span>) {
+        for node in nodes {
+            out.push(node.span);
+            if let NodeKind::VarUse { name_span, .. } = &node.kind {
+                out.push(*name_span);
+            }
+            for children in node.child_lists() {
+                spans(children, out);
+            }
+        }
+    }
+
+    #[test]
+    fn includes_a_program_without_its_head_and_info() {
+        let mut nodes = parse("#CAN HAS include/Neighbor.lol #MKAY").unwrap();
+        let directive = nodes[0].span;
+        let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/Test38.lol");
+        let mut includer = Includer::new(Some(&file));
+        includer.resolve(&mut nodes);
+
+        let warnings: Vec<_> = includer.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("#MAEK INFO of included file"));
+        assert!(warnings[1].contains("#MAEK HEAD of included file"));
+        assert!(!nodes.iter().any(|node| {
+            matches!(node.kind, NodeKind::Head(_) | NodeKind::Metadata(_))
+        }));
+
+        // Citations and macro arguments are moved to the directive too
+        assert!(nodes.iter().any(|node| {
+            matches!(node.kind, NodeKind::Quote { citation: Some(_), .. })
+        }));
+        assert!(nodes.iter().any(|node| {
+            matches!(&node.kind, NodeKind::MacroCall { args, .. } if !args.is_empty())
+        }));
+        let mut found = Vec::new();
+        spans(&nodes, &mut found);
+        assert!(found.iter().all(|span| *span == directive));
+    }
+}
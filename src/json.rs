@@ -0,0 +1,353 @@
+use std::fmt;
+
+/// Minimal JSON value, enough for the language server's JSON-RPC messages.
+///
+/// Objects keep their keys in insertion order so the output is predictable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Build an object from key/value pairs
+    pub fn object(pairs: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn string(s: impl Into<String>) -> Json {
+        Json::String(s.into())
+    }
+
+    pub fn number(n: usize) -> Json {
+        Json::Number(n as f64)
+    }
+
+    /// Member of an object, or None for missing keys and non-objects
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Follow a chain of object keys, e.g. `["textDocument", "uri"]`
+    pub fn path(&self, keys: &[&str]) -> Option<&Json> {
+        keys.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Parse a complete JSON text
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut reader = Reader {
+            chars: text.chars().collect(),
+            position: 0,
+        };
+        let value = reader.read_value()?;
+        reader.skip_whitespace();
+        if reader.position != reader.chars.len() {
+            return Err(format!("Unexpected data at offset {}", reader.position));
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Character-by-character recursive descent reader for JSON text
+struct Reader {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at offset {}", c, self.position))
+        }
+    }
+
+    fn expect_word(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+
+    fn read_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.read_object(),
+            Some('[') => self.read_array(),
+            Some('"') => self.read_string().map(Json::String),
+            Some('t') => self.expect_word("true", Json::Bool(true)),
+            Some('f') => self.expect_word("false", Json::Bool(false)),
+            Some('n') => self.expect_word("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.read_number(),
+            _ => Err(format!("Unexpected character at offset {}", self.position)),
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut pairs = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(pairs));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.read_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.read_value()?;
+            pairs.push((key, value));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(pairs));
+                }
+                _ => return Err(format!("Expected ',' or '}}' at offset {}", self.position)),
+            }
+        }
+    }
+
+    fn read_array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.read_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(format!("Expected ',' or ']' at offset {}", self.position)),
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or("Unterminated string")?;
+            self.position += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.peek().ok_or("Unterminated string")?;
+                    self.position += 1;
+                    match escape {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => s.push(self.read_unicode_escape()?),
+                        other => s.push(other),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// The four hex digits after `\u`, combining surrogate pairs
+    fn read_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.read_hex4()?;
+        if (0xD800..0xDC00).contains(&high) && self.chars[self.position..].starts_with(&['\\', 'u']) {
+            self.position += 2;
+            let low = self.read_hex4()?;
+            let code = 0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+            return Ok(char::from_u32(code).unwrap_or('\u{FFFD}'));
+        }
+        Ok(char::from_u32(high).unwrap_or('\u{FFFD}'))
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, String> {
+        let end = self.position + 4;
+        if end > self.chars.len() {
+            return Err("Truncated unicode escape".to_string());
+        }
+        let digits: String = self.chars[self.position..end].iter().collect();
+        self.position = end;
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("Bad unicode escape '{}'", digits))
+    }
+
+    fn read_number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while matches!(self.peek(), Some(c) if c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' || c.is_ascii_digit())
+        {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse::<f64>()
+            .map(Json::Number)
+            .map_err(|_| format!("Bad number '{}'", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    #[test]
+    fn parses_string_escapes() {
+        let value = Json::parse(r#""a\"b\\c\/d\n\t\r\b\f""#).unwrap();
+        assert_eq!(value, Json::String("a\"b\\c/d\n\t\r\u{8}\u{c}".to_string()));
+    }
+
+    #[test]
+    fn parses_unicode_escapes_and_surrogate_pairs() {
+        assert_eq!(Json::parse(r#""caf\u00e9""#).unwrap(), Json::string("café"));
+        assert_eq!(Json::parse(r#""\ud83d\ude00!""#).unwrap(), Json::string("😀!"));
+        // A lone high surrogate cannot be a char
+        assert_eq!(Json::parse(r#""\ud83d""#).unwrap(), Json::string("\u{FFFD}"));
+    }
+
+    #[test]
+    fn parses_numbers() {
+        assert_eq!(Json::parse("0").unwrap(), Json::Number(0.0));
+        assert_eq!(Json::parse("-12").unwrap(), Json::Number(-12.0));
+        assert_eq!(Json::parse("3.25").unwrap(), Json::Number(3.25));
+        assert_eq!(Json::parse("1e3").unwrap(), Json::Number(1000.0));
+        assert_eq!(Json::parse("2.5E-1").unwrap(), Json::Number(0.25));
+        assert_eq!(Json::parse("[1, 2]").unwrap().as_array().map(<[Json]>::len), Some(2));
+    }
+
+    #[test]
+    fn parses_nested_objects() {
+        let value = Json::parse(r#" {"a": {"b": [true, false, null]}, "c": "d"} "#).unwrap();
+        let flags = Json::Array(vec![Json::Bool(true), Json::Bool(false), Json::Null]);
+        assert_eq!(value.path(&["a", "b"]), Some(&flags));
+        assert_eq!(value.get("c").and_then(Json::as_str), Some("d"));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in [
+            "",
+            "{",
+            "[1,",
+            "[1 2]",
+            r#"{"a" 1}"#,
+            r#"{a: 1}"#,
+            r#""unterminated"#,
+            r#""\u12""#,
+            r#""\uzzzz""#,
+            "1.2.3",
+            "-",
+            "tru",
+            "nul",
+            "[] []",
+        ] {
+            assert!(Json::parse(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let text = r#"{"s":"a\"b\\\n\u0001","n":-1.5,"i":42,"l":[null,true]}"#;
+        assert_eq!(Json::parse(text).unwrap().to_string(), text);
+    }
+}
@@ -1,4 +1,90 @@
-use std::process;
+use crate::diagnostic::{Diagnostic, Span};
+
+/// Keywords whose block content is taken verbatim up to the next #OIC,
+/// with the name of the block for errors
+const RAW_BLOCKS: &[(&str, &str)] = &[
+    ("#MAEK KODEZ", "Code block"),
+    ("#MAEK STYLE", "Style block"),
+    ("#MAEK SCRIPT", "Script block"),
+];
+
+/// Every keyword the language accepts, in its canonical upper-case spelling.
+/// Matching is case-insensitive, so `#hai` and `#HAI` are the same token.
+pub const KEYWORDS: &[&str] = &[
+    "#HAI",
+    "#KTHXBYE",
+    "#OBTW",
+    "#TLDR",
+    "#MAEK INFO",
+    "#GIMMEH AUTHOR",
+    "#GIMMEH DATE",
+    "#GIMMEH DESCRIPSHUN",
+    "#GIMMEH KEYWURDZ",
+    "#GIMMEH TAGZ",
+    "#MAEK HEAD",
+    "#GIMMEH STYLESHEET",
+    "#MAEK STYLE",
+    "#GIMMEH SCRIPT",
+    "#MAEK SCRIPT",
+    "#GIMMEH ICON",
+    "#GIMMEH META",
+    "#MAEK HEADR",
+    "#MAEK PARAGRAF",
+    "#MAEK LIST",
+    "#MAEK NUMBRD LIST",
+    "#MAEK TABUL",
+    "#MAEK KODEZ",
+    "#MAEK QUOTE",
+    "#GIMMEH CITE",
+    "#GIMMEH LINE",
+    "#GIMMEH ITALICS",
+    "#GIMMEH TITLE",
+    "#GIMMEH ITEM",
+    "#GIMMEH HEDR ROW",
+    "#GIMMEH ROW",
+    "#GIMMEH CELL",
+    "#GIMMEH NEWLINE",
+    "#GIMMEH SOUNDZ",
+    "#GIMMEH VIDZ",
+    "#GIMMEH BOLD",
+    "#GIMMEH KODE",
+    "#GIMMEH STRIKE",
+    "#GIMMEH UNDERLYNE",
+    "#GIMMEH SUBSKRIPT",
+    "#GIMMEH SUPRSKRIPT",
+    "#GIMMEH HILITE",
+    "#GIMMEH LINX",
+    "#GIMMEH PIKCHUR",
+    "#GIMMEH TOC",
+    "#MKAY",
+    "#OIC",
+    "#I HAZ",
+    "#I ALWAYS HAZ",
+    "#NAO",
+    "#R",
+    "#HOW IZ I",
+    "#YR",
+    "#AN YR",
+    "#IF U SAY SO",
+    "#I IZ",
+    "#CAN HAS",
+    "#O RLY",
+    "#YA RLY",
+    "#NO WAI",
+    "#IM IN YR",
+    "#OV",
+    "#FRUM",
+    "#TIL",
+    "#IM OUTTA YR",
+    "#SUM OF",
+    "#DIFF OF",
+    "#PRODUKT OF",
+    "#SMOOSH",
+    "#BOTH SAEM",
+    "#AN",
+    "#IT IZ",
+    "#LEMME SEE",
+];
 
 /// Trait for a simple lexical analyzer
 pub trait LexicalAnalyzer {
@@ -7,12 +93,37 @@ pub trait LexicalAnalyzer {
     fn lookup(&self, s: &str) -> bool;
 }
 
+/// A lexeme together with where it was found in the source
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Token {
+    pub text: String,
+    pub span: Span,
+    /// Verbatim code, style or script block content, taken as-is even if
+    /// empty or starting with '#'
+    pub raw: bool,
+}
+
+impl Token {
+    /// The empty token returned once the end of the source is reached
+    pub fn is_eof(&self) -> bool {
+        self.text.is_empty() && !self.raw
+    }
+
+    /// True for keyword tokens (anything beginning with '#')
+    pub fn is_keyword(&self) -> bool {
+        self.text.starts_with('#') && !self.raw
+    }
+}
+
 /// Lexical Analyzer implementation
 pub struct Lexer {
     source: Vec<char>,
     position: usize,
     current_char: char,
     buffer: String,
+    /// Set after #MAEK KODEZ and the other raw blocks: the next token is
+    /// the block's raw content. Holds the block's name for errors.
+    raw_pending: Option<&'static str>,
 }
 
 impl Lexer {
@@ -24,10 +135,11 @@ impl Lexer {
             position: 0,
             current_char: '\0',
             buffer: String::new(),
+            raw_pending: None,
         }
     }
 
-    pub fn next_token(&mut self) -> String {
+    pub fn next_token(&mut self) -> Result<Token, Diagnostic> {
         self.buffer.clear();
 
         // Initialize current_char on first call
@@ -35,22 +147,54 @@ impl Lexer {
             self.get_char();
         }
 
+        if let Some(block) = self.raw_pending.take() {
+            return self.read_raw(block);
+        }
+
         while self.current_char.is_whitespace() && self.current_char != '\0' {
             self.get_char();
         }
 
         if self.current_char == '\0' {
-            return String::new();
+            let end = self.source.len();
+            return Ok(Token {
+                text: String::new(),
+                span: Span::new(end, end),
+                raw: false,
+            });
         }
 
         if self.current_char == '#' {
             return self.read_keyword();
         }
 
-        self.read_text()
+        Ok(self.read_text())
     }
 
-    fn read_keyword(&mut self) -> String {
+    /// Read every remaining token, stopping at the end of the source or at
+    /// the first lexical error
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, Diagnostic> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            if token.is_eof() {
+                return Ok(tokens);
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Offset of `current_char` within the source
+    fn offset(&self) -> usize {
+        if self.current_char == '\0' {
+            self.source.len()
+        } else {
+            self.position - 1
+        }
+    }
+
+    fn read_keyword(&mut self) -> Result<Token, Diagnostic> {
+        let start = self.offset();
         self.add_char(self.current_char);
         self.get_char();
 
@@ -59,40 +203,150 @@ impl Lexer {
             self.get_char();
         }
 
-        let current_buffer = self.buffer.clone().to_lowercase();
+        // Multi-word keywords: keep reading words for as long as what we
+        // have so far is the start of a longer keyword (#MAEK -> #MAEK
+        // NUMBRD -> #MAEK NUMBRD LIST)
+        while self.is_keyword_prefix(&self.buffer) {
+            // A keyword can also be the start of a longer one (#AN and
+            // #AN YR); remember where it ended in case the next word
+            // turns out to be text
+            let complete = self.lookup(&self.buffer);
+            let (position, current_char, length) =
+                (self.position, self.current_char, self.buffer.len());
 
-        if matches!(
-            current_buffer.as_str(),
-            "#maek" | "#gimmeh" | "#i" | "#it" | "#lemme"
-        ) {
             while self.current_char == ' ' || self.current_char == '\t' {
                 self.add_char(self.current_char);
                 self.get_char();
             }
 
+            let continues = self.current_char.is_alphabetic();
             while self.current_char.is_alphabetic() {
                 self.add_char(self.current_char);
                 self.get_char();
             }
+
+            if !continues
+                || (complete && !self.lookup(&self.buffer) && !self.is_keyword_prefix(&self.buffer))
+            {
+                self.position = position;
+                self.current_char = current_char;
+                self.buffer.truncate(length);
+                break;
+            }
         }
 
-        let token = self.buffer.clone();
+        let token = Token {
+            text: self.buffer.clone(),
+            span: Span::new(start, self.offset()),
+            raw: false,
+        };
 
-        if !self.lookup(&token) {
-            eprintln!("Lexical error: '{}' is not a valid keyword", token);
-            process::exit(1);
+        if !self.lookup(&token.text) {
+            return Err(Diagnostic::lexical(
+                format!("'{}' is not a valid keyword", token.text),
+                token.span,
+            ));
         }
 
-        token
+        self.raw_pending = RAW_BLOCKS
+            .iter()
+            .find(|(keyword, _)| keyword.eq_ignore_ascii_case(&token.text))
+            .map(|(_, block)| *block);
+
+        Ok(token)
+    }
+
+    /// True if `s` followed by another word could still become a keyword
+    fn is_keyword_prefix(&self, s: &str) -> bool {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        KEYWORDS.iter().any(|keyword| {
+            let keyword_words: Vec<&str> = keyword.split(' ').collect();
+            keyword_words.len() > words.len()
+                && keyword_words
+                    .iter()
+                    .zip(&words)
+                    .all(|(k, w)| k.eq_ignore_ascii_case(w))
+        })
+    }
+
+    fn read_text(&mut self) -> Token {
+        let start = self.offset();
+        while self.current_char != '\0' && !(self.current_char == '#' && self.at_keyword_in_text()) {
+            self.add_char(self.current_char);
+            self.get_char();
+        }
+
+        // Leading whitespace was already skipped, so only the tail moves
+        let text = self.buffer.trim().to_string();
+        Token {
+            span: Span::new(start, start + text.chars().count()),
+            text,
+            raw: false,
+        }
     }
 
-    fn read_text(&mut self) -> String {
-        while self.current_char != '\0' && self.current_char != '#' {
+    /// True if the '#' at the current position starts a keyword. Inside a
+    /// word it only does if a keyword follows, so `page.lol#top` and
+    /// `C#` stay text while `H#GIMMEH SUBSKRIPT` still splits.
+    fn at_keyword_in_text(&self) -> bool {
+        let start = self.offset();
+        let after_space = start == 0 || self.source[start - 1].is_whitespace();
+        if after_space {
+            return true;
+        }
+        let word: String = std::iter::once('#')
+            .chain(self.source[start + 1..].iter().copied().take_while(|c| c.is_alphabetic()))
+            .collect();
+        word.len() > 1 && (self.lookup(&word) || self.is_keyword_prefix(&word))
+    }
+
+    /// Everything up to the next #OIC, untouched: whitespace is kept and
+    /// '#' does not start a keyword. Used for the body of #MAEK KODEZ,
+    /// #MAEK STYLE and #MAEK SCRIPT. A `\#OIC` in the content stands for a
+    /// literal #OIC that does not end the block.
+    fn read_raw(&mut self, block: &str) -> Result<Token, Diagnostic> {
+        let start = self.offset();
+        while !self.at_raw_end() {
+            if self.current_char == '\0' {
+                let end = self.source.len();
+                return Err(Diagnostic::lexical(
+                    format!("{} is missing its closing #OIC", block),
+                    Span::new(end, end),
+                ));
+            }
+            if self.at_escaped_raw_end() {
+                // Drop the backslash and keep the #OIC as content
+                self.get_char();
+                for _ in 0..4 {
+                    self.add_char(self.current_char);
+                    self.get_char();
+                }
+                continue;
+            }
             self.add_char(self.current_char);
             self.get_char();
         }
 
-        self.buffer.trim().to_string()
+        Ok(Token {
+            text: self.buffer.clone(),
+            span: Span::new(start, self.offset()),
+            raw: true,
+        })
+    }
+
+    /// True if the source continues with a standalone #OIC here
+    fn at_raw_end(&self) -> bool {
+        let start = self.offset();
+        let word: String = self.source.iter().skip(start).take(4).collect();
+        let after = self.source.get(start + 4).copied().unwrap_or(' ');
+        word.eq_ignore_ascii_case("#oic") && !after.is_alphabetic()
+    }
+
+    /// True if the source continues with `\#OIC` here
+    fn at_escaped_raw_end(&self) -> bool {
+        let start = self.offset();
+        let word: String = self.source.iter().skip(start + 1).take(4).collect();
+        self.current_char == '\\' && word.eq_ignore_ascii_case("#oic")
     }
 }
 
@@ -113,28 +367,6 @@ impl LexicalAnalyzer for Lexer {
     }
 
     fn lookup(&self, s: &str) -> bool {
-        let lower = s.to_lowercase();
-        matches!(
-            lower.as_str(),
-            "#hai"
-                | "#kthxbye"
-                | "#obtw"
-                | "#tldr"
-                | "#maek head"
-                | "#maek paragraf"
-                | "#maek list"
-                | "#gimmeh italics"
-                | "#gimmeh title"
-                | "#gimmeh item"
-                | "#gimmeh newline"
-                | "#gimmeh soundz"
-                | "#gimmeh vidz"
-                | "#gimmeh bold"
-                | "#mkay"
-                | "#oic"
-                | "#i haz"
-                | "#it iz"
-                | "#lemme see"
-        )
+        KEYWORDS.iter().any(|keyword| keyword.eq_ignore_ascii_case(s))
     }
 }
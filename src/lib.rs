@@ -0,0 +1,21 @@
+//! LOLCODE to HTML compiler.
+//!
+//! `compiler::LolCompiler` runs the whole pipeline on a source text: the
+//! lexer, the parser, file inclusion and the SemanticAnalyzer that emits
+//! HTML. After `analyze` its diagnostics, output, symbols and the
+//! document's #MAEK INFO metadata can be read back.
+
+pub mod ast;
+pub mod compiler;
+pub mod diagnostic;
+mod expression;
+mod image;
+mod include;
+mod json;
+mod lexer;
+pub mod lsp;
+pub mod output;
+mod parser;
+pub mod repl;
+pub mod semantic;
+pub mod theme;
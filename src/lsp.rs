@@ -0,0 +1,801 @@
+use crate::ast::{Node, NodeKind};
+use crate::compiler::LolCompiler;
+use crate::diagnostic::{LineIndex, Severity, Span};
+use crate::json::Json;
+use crate::lexer::KEYWORDS;
+use crate::semantic::Symbol;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+/// Language Server Protocol front end, started with `lolcompiler lsp`.
+///
+/// Speaks JSON-RPC over stdin/stdout. Every open document is recompiled on
+/// each change with the same lexer, parser and SemanticAnalyzer the
+/// command line uses, so editors see exactly the errors `lolcompiler`
+/// would report. Supported requests:
+/// - publishDiagnostics after didOpen/didChange
+/// - completion of keywords and in-scope variable names
+/// - hover on a variable, showing its value and definition site
+/// - go-to-definition from #LEMME SEE to the matching #I HAZ
+/// - document symbols for paragraphs and lists
+pub struct LanguageServer {
+    documents: HashMap<String, OpenDocument>,
+    shutdown_requested: bool,
+}
+
+struct OpenDocument {
+    text: String,
+    /// Definitions from the last version that parsed, so variable
+    /// completion keeps working while the user is mid-edit
+    last_symbols: Vec<Symbol>,
+}
+
+/// Result of compiling one open document for a single request
+struct Analysis {
+    lines: LineIndex,
+    compiler: LolCompiler,
+    document: Option<crate::ast::Document>,
+}
+
+// JSON-RPC error codes
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Largest message body accepted, so a bad Content-Length header cannot
+/// make the server allocate gigabytes
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+// LSP enumerations used below
+const SYNC_FULL: usize = 1;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_KEYWORD: usize = 14;
+const SYMBOL_NAMESPACE: usize = 3;
+const SYMBOL_STRING: usize = 15;
+const SYMBOL_ARRAY: usize = 18;
+
+/// Run the server until the client sends `exit`. Returns the process exit
+/// code the protocol asks for: 0 if `shutdown` came first, 1 otherwise.
+pub fn run() -> io::Result<i32> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+    let mut server = LanguageServer::new();
+
+    while let Some(message) = read_message(&mut input)? {
+        let message = match Json::parse(&message) {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!("lsp: ignoring malformed message: {}", err);
+                continue;
+            }
+        };
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        if method == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(if server.shutdown_requested { 0 } else { 1 })
+}
+
+/// Read one `Content-Length` framed message, or None at end of input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without Content-Length header",
+        ));
+    };
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is over the {} byte limit", length, MAX_MESSAGE_LENGTH),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+impl LanguageServer {
+    pub fn new() -> Self {
+        Self {
+            documents: HashMap::new(),
+            shutdown_requested: false,
+        }
+    }
+
+    /// Handle one incoming message and return the messages to send back
+    /// (a response for requests, diagnostics for document changes)
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let id = message.get("id").cloned();
+
+        let result = match method {
+            "initialize" => Ok(Some(capabilities())),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Some(Json::Null))
+            }
+            "textDocument/didOpen" => {
+                let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str);
+                let text = params.path(&["textDocument", "text"]).and_then(Json::as_str);
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    return self.update(uri, text.to_string());
+                }
+                Ok(None)
+            }
+            "textDocument/didChange" => {
+                let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str);
+                // Full synchronisation: the last change holds the whole text
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    return self.update(uri, text.to_string());
+                }
+                Ok(None)
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params.path(&["textDocument", "uri"]).and_then(Json::as_str) {
+                    self.documents.remove(uri);
+                    return vec![publish_diagnostics(uri, Vec::new())];
+                }
+                Ok(None)
+            }
+            "textDocument/completion" => self.with_position(&params, Self::completion),
+            "textDocument/hover" => self.with_position(&params, Self::hover),
+            "textDocument/definition" => self.with_position(&params, Self::definition),
+            "textDocument/documentSymbol" => {
+                match params.path(&["textDocument", "uri"]).and_then(Json::as_str) {
+                    Some(uri) => Ok(Some(self.document_symbols(uri))),
+                    None => Err((INVALID_PARAMS, "missing textDocument.uri".to_string())),
+                }
+            }
+            _ if id.is_some() => Err((METHOD_NOT_FOUND, format!("unsupported method '{}'", method))),
+            // Unknown notifications (initialized, $/cancelRequest, ...) are ignored
+            _ => Ok(None),
+        };
+
+        let Some(id) = id else {
+            return Vec::new();
+        };
+        let response = match result {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id),
+                ("result", result.unwrap_or(Json::Null)),
+            ]),
+            Err((code, message)) => Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id),
+                (
+                    "error",
+                    Json::object(vec![
+                        ("code", Json::Number(code as f64)),
+                        ("message", Json::string(message)),
+                    ]),
+                ),
+            ]),
+        };
+        vec![response]
+    }
+
+    /// Store new document text and publish its diagnostics
+    fn update(&mut self, uri: &str, text: String) -> Vec<Json> {
+        let analysis = analyze(uri, &text);
+        let diagnostics = analysis
+            .compiler
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| {
+                let severity = match diagnostic.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                };
+                Json::object(vec![
+                    ("range", range(&analysis.lines, diagnostic.span)),
+                    ("severity", Json::number(severity)),
+                    ("source", Json::string("lolcompiler")),
+                    ("message", Json::string(diagnostic.to_string())),
+                ])
+            })
+            .collect();
+
+        let entry = self.documents.entry(uri.to_string()).or_insert(OpenDocument {
+            text: String::new(),
+            last_symbols: Vec::new(),
+        });
+        entry.text = text;
+        if analysis.document.is_some() {
+            entry.last_symbols = analysis.compiler.semantic().symbols().to_vec();
+        }
+
+        vec![publish_diagnostics(uri, diagnostics)]
+    }
+
+    /// Decode `textDocument` + `position` params and run `handler` on the
+    /// freshly analyzed document at that character offset
+    fn with_position(
+        &self,
+        params: &Json,
+        handler: fn(&Self, &str, &Analysis, usize) -> Json,
+    ) -> Result<Option<Json>, (i64, String)> {
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str);
+        let line = params.path(&["position", "line"]).and_then(Json::as_usize);
+        let character = params.path(&["position", "character"]).and_then(Json::as_usize);
+        let (Some(uri), Some(line), Some(character)) = (uri, line, character) else {
+            return Err((INVALID_PARAMS, "missing textDocument or position".to_string()));
+        };
+        let Some(open) = self.documents.get(uri) else {
+            return Ok(None);
+        };
+
+        let analysis = analyze(uri, &open.text);
+        let offset = analysis.lines.offset_utf16(line, character);
+        Ok(Some(handler(self, uri, &analysis, offset)))
+    }
+
+    fn completion(&self, uri: &str, analysis: &Analysis, offset: usize) -> Json {
+        let text: Vec<char> = self.documents[uri].text.chars().collect();
+        let mut items = Vec::new();
+
+        // Replace a partially typed keyword, '#' included, so the client
+        // doesn't end up with "##GIMMEH"
+        let keyword_start = partial_keyword_start(&text, offset);
+        for keyword in KEYWORDS {
+            let mut item = vec![
+                ("label", Json::string(*keyword)),
+                ("kind", Json::number(COMPLETION_KEYWORD)),
+            ];
+            if let Some(start) = keyword_start {
+                item.push((
+                    "textEdit",
+                    Json::object(vec![
+                        ("range", range(&analysis.lines, Span::new(start, offset))),
+                        ("newText", Json::string(*keyword)),
+                    ]),
+                ));
+            }
+            items.push(Json::object(item));
+        }
+
+        // Variables visible here; later (inner) definitions shadow earlier ones
+        let symbols = if analysis.document.is_some() {
+            analysis.compiler.semantic().symbols()
+        } else {
+            &self.documents[uri].last_symbols[..]
+        };
+        let mut visible: Vec<&Symbol> = Vec::new();
+        for symbol in symbols.iter().filter(|s| s.visible.contains(offset)) {
+            visible.retain(|s| s.name != symbol.name);
+            visible.push(symbol);
+        }
+        for symbol in visible {
+            items.push(Json::object(vec![
+                ("label", Json::string(symbol.name.clone())),
+                ("kind", Json::number(COMPLETION_VARIABLE)),
+                ("detail", Json::string(symbol.variable.text())),
+            ]));
+        }
+
+        Json::Array(items)
+    }
+
+    fn hover(&self, _uri: &str, analysis: &Analysis, offset: usize) -> Json {
+        let semantic = analysis.compiler.semantic();
+
+        let target = semantic
+            .references()
+            .iter()
+            .find(|r| r.span.contains(offset))
+            .map(|r| (r.name.clone(), r.span, r.resolved.clone()))
+            .or_else(|| {
+                semantic
+                    .symbols()
+                    .iter()
+                    .find(|s| s.variable.definition.contains(offset))
+                    .map(|s| (s.name.clone(), s.variable.definition, Some(s.variable.clone())))
+            });
+
+        let Some((name, span, variable)) = target else {
+            return Json::Null;
+        };
+        let contents = match variable {
+            Some(variable) => {
+                let (line, column) = analysis.lines.line_col(variable.definition.start);
+                format!(
+                    "**{}** = `{}`\n\nDefined at line {}, column {}",
+                    name,
+                    variable.text(),
+                    line + 1,
+                    column + 1
+                )
+            }
+            None => format!("**{}** is not defined in this scope", name),
+        };
+
+        Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![
+                    ("kind", Json::string("markdown")),
+                    ("value", Json::string(contents)),
+                ]),
+            ),
+            ("range", range(&analysis.lines, span)),
+        ])
+    }
+
+    fn definition(&self, uri: &str, analysis: &Analysis, offset: usize) -> Json {
+        let definition = analysis
+            .compiler
+            .semantic()
+            .references()
+            .iter()
+            .find(|r| r.span.contains(offset))
+            .and_then(|r| r.resolved.as_ref())
+            .map(|variable| variable.definition);
+
+        match definition {
+            Some(span) => Json::object(vec![
+                ("uri", Json::string(uri)),
+                ("range", range(&analysis.lines, span)),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    fn document_symbols(&self, uri: &str) -> Json {
+        let Some(open) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        let analysis = analyze(uri, &open.text);
+        match &analysis.document {
+            Some(document) => Json::Array(block_symbols(&analysis.lines, &document.body)),
+            None => Json::Array(Vec::new()),
+        }
+    }
+}
+
+impl Default for LanguageServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn analyze(uri: &str, text: &str) -> Analysis {
+    let mut compiler = LolCompiler::new();
+    // Included files are found relative to the document when it is on disk
+    if let Some(path) = file_path(uri) {
+        compiler.set_source_file(&path);
+    }
+    let document = compiler.analyze(text);
+    Analysis {
+        lines: LineIndex::new(text),
+        compiler,
+        document,
+    }
+}
+
+/// Local path of a `file://` URI, with percent escapes such as `%20`
+/// decoded and, on Windows, the slash before a drive letter removed
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match escape.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let path = String::from_utf8_lossy(&decoded).into_owned();
+    let drive = path.as_bytes();
+    if cfg!(windows) && drive.len() >= 3 && drive[0] == b'/' && drive[1].is_ascii_alphabetic() && drive[2] == b':'
+    {
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                ("textDocumentSync", Json::number(SYNC_FULL)),
+                (
+                    "completionProvider",
+                    Json::object(vec![(
+                        "triggerCharacters",
+                        Json::Array(vec![Json::string("#")]),
+                    )]),
+                ),
+                ("hoverProvider", Json::Bool(true)),
+                ("definitionProvider", Json::Bool(true)),
+                ("documentSymbolProvider", Json::Bool(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![
+                ("name", Json::string("lolcompiler")),
+                ("version", Json::string(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("method", Json::string("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", Json::string(uri)),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+fn position(lines: &LineIndex, offset: usize) -> Json {
+    let (line, character) = lines.line_col_utf16(offset);
+    Json::object(vec![
+        ("line", Json::number(line)),
+        ("character", Json::number(character)),
+    ])
+}
+
+fn range(lines: &LineIndex, span: Span) -> Json {
+    Json::object(vec![
+        ("start", position(lines, span.start)),
+        ("end", position(lines, span.end)),
+    ])
+}
+
+/// Offset of the '#' starting the keyword being typed at `offset`, if the
+/// cursor sits right after one (e.g. `#GIMMEH BO|`)
+fn partial_keyword_start(text: &[char], offset: usize) -> Option<usize> {
+    let mut start = offset.min(text.len());
+    while start > 0 {
+        let c = text[start - 1];
+        if c == '#' {
+            return Some(start - 1);
+        }
+        if !(c.is_alphabetic() || c == ' ' || c == '\t') {
+            return None;
+        }
+        start -= 1;
+    }
+    None
+}
+
+/// DocumentSymbol entries for the paragraphs and lists in `nodes`
+fn block_symbols(lines: &LineIndex, nodes: &[Node]) -> Vec<Json> {
+    let mut symbols = Vec::new();
+    for node in nodes {
+        let (name, kind, detail) = match &node.kind {
+            NodeKind::Heading { children, .. } => ("Heading", SYMBOL_STRING, excerpt(children)),
+            NodeKind::Paragraph(children) => ("Paragraph", SYMBOL_NAMESPACE, excerpt(children)),
+            NodeKind::List { ordered, items } => (
+                if *ordered { "Numbered list" } else { "List" },
+                SYMBOL_ARRAY,
+                format!("{} items", items.len()),
+            ),
+            // Lists nested in items belong to the enclosing list
+            _ => {
+                for children in node.child_lists() {
+                    symbols.extend(block_symbols(lines, children));
+                }
+                continue;
+            }
+        };
+        symbols.push(Json::object(vec![
+            ("name", Json::string(name)),
+            ("detail", Json::string(detail)),
+            ("kind", Json::number(kind)),
+            ("range", range(lines, node.span)),
+            ("selectionRange", range(lines, node.span)),
+            ("children", Json::Array(block_symbols(lines, node.children()))),
+        ]));
+    }
+    symbols
+}
+
+/// The first plain text found depth-first, e.g. inside bold text
+fn first_text(nodes: &[Node]) -> Option<&String> {
+    nodes.iter().find_map(|node| match &node.kind {
+        NodeKind::Text(text) => Some(text),
+        _ => node.child_lists().into_iter().find_map(first_text),
+    })
+}
+
+/// The first few words of a paragraph, used as its symbol detail
+fn excerpt(children: &[Node]) -> String {
+    const MAX: usize = 30;
+    let text = first_text(children);
+    match text {
+        Some(text) if text.chars().count() > MAX => {
+            format!("{}...", text.chars().take(MAX).collect::<String>())
+        }
+        Some(text) => text.clone(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn request(id: usize, method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("id", Json::number(id)),
+            ("method", Json::string(method)),
+            ("params", params),
+        ])
+    }
+
+    #[test]
+    fn reads_content_length_framed_messages() {
+        let text = "Content-Length: 2\r\n\r\n{}\
+                    content-length:  6\r\nContent-Type: x\r\n\r\n[\"é\"]";
+        let mut input = Cursor::new(text.as_bytes());
+        assert_eq!(read_message(&mut input).unwrap(), Some("{}".to_string()));
+        // The length is in bytes, not characters
+        assert_eq!(read_message(&mut input).unwrap(), Some("[\"é\"]".to_string()));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_messages_without_content_length() {
+        let mut input = Cursor::new("Content-Type: x\r\n\r\n{}".as_bytes());
+        assert!(read_message(&mut input).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        let text = format!("Content-Length: {}\r\n\r\n{{}}", MAX_MESSAGE_LENGTH + 1);
+        let error = read_message(&mut Cursor::new(text.into_bytes())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let message = request(1, "shutdown", Json::Null);
+        let mut output = Vec::new();
+        write_message(&mut output, &message).unwrap();
+        let text = read_message(&mut Cursor::new(output)).unwrap().unwrap();
+        assert_eq!(Json::parse(&text).unwrap(), message);
+    }
+
+    #[test]
+    fn decodes_file_uris() {
+        assert_eq!(
+            file_path("file:///home/me/My%20Site/index.lol"),
+            Some(PathBuf::from("/home/me/My Site/index.lol"))
+        );
+        assert_eq!(file_path("file:///tmp/caf%C3%A9%2"), Some(PathBuf::from("/tmp/café%2")));
+        assert_eq!(file_path("untitled:Untitled-1"), None);
+    }
+
+    /// A server with `text` open as `uri`, checking the diagnostics sent back
+    fn open(uri: &str, text: &str) -> LanguageServer {
+        let mut server = LanguageServer::new();
+        let opened = server.handle(&Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string("textDocument/didOpen")),
+            (
+                "params",
+                Json::object(vec![(
+                    "textDocument",
+                    Json::object(vec![("uri", Json::string(uri)), ("text", Json::string(text))]),
+                )]),
+            ),
+        ]));
+        assert_eq!(opened.len(), 1);
+        let method = opened[0].get("method").and_then(Json::as_str);
+        assert_eq!(method, Some("textDocument/publishDiagnostics"));
+        assert_eq!(opened[0].path(&["params", "diagnostics"]), Some(&Json::Array(Vec::new())));
+        server
+    }
+
+    /// The result of request `method` at a position in `uri`
+    fn at(server: &mut LanguageServer, method: &str, uri: &str, line: usize, column: usize) -> Json {
+        let response = server.handle(&request(
+            9,
+            method,
+            Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", Json::string(uri))])),
+                ("position", position(line, column)),
+            ]),
+        ));
+        assert_eq!(response.len(), 1);
+        assert_eq!(response[0].get("id"), Some(&Json::number(9)));
+        response[0].get("result").cloned().unwrap()
+    }
+
+    fn position(line: usize, character: usize) -> Json {
+        Json::object(vec![("line", Json::number(line)), ("character", Json::number(character))])
+    }
+
+    const PROGRAM: &str = "#HAI\n\
+                           #I HAZ naem #IT IZ Homer #MKAY\n\
+                           #MAEK HEADR 1 Menu #MKAY\n\
+                           #MAEK PARAGRAF\n\
+                           \x20   #I HAZ drink #IT IZ Duff #MKAY\n\
+                           \x20   #LEMME SEE naem #MKAY likes #LEMME SEE drink #MKAY\n\
+                           #OIC\n\
+                           #MAEK LIST #GIMMEH ITEM a #MKAY #GIMMEH ITEM b #MKAY #OIC\n\
+                           #KTHXBYE\n";
+
+    #[test]
+    fn completes_keywords_and_visible_variables() {
+        let uri = "untitled:complete.lol";
+        let mut server = open(uri, PROGRAM);
+
+        // After "#LEMME SEE " on line 5: both variables are visible
+        let result = at(&mut server, "textDocument/completion", uri, 5, 15);
+        let items = result.as_array().unwrap();
+        let label = |item: &Json| item.get("label").and_then(Json::as_str).map(str::to_string);
+        let variables: Vec<String> = items
+            .iter()
+            .filter(|item| item.get("kind") == Some(&Json::number(COMPLETION_VARIABLE)))
+            .filter_map(label)
+            .collect();
+        assert_eq!(variables, ["naem", "drink"]);
+        assert!(items.iter().filter_map(label).any(|label| label == "#GIMMEH BOLD"));
+
+        // On line 2, before the paragraph, only naem is; "#MAE" is replaced
+        let result = at(&mut server, "textDocument/completion", uri, 2, 4);
+        let items = result.as_array().unwrap();
+        let variables: Vec<String> = items
+            .iter()
+            .filter(|item| item.get("kind") == Some(&Json::number(COMPLETION_VARIABLE)))
+            .filter_map(label)
+            .collect();
+        assert_eq!(variables, ["naem"]);
+        let keyword = items.iter().find(|item| label(item).as_deref() == Some("#MAEK HEADR")).unwrap();
+        assert_eq!(keyword.path(&["textEdit", "range", "start"]), Some(&position(2, 0)));
+        assert_eq!(keyword.path(&["textEdit", "range", "end"]), Some(&position(2, 4)));
+    }
+
+    #[test]
+    fn finds_definitions() {
+        let uri = "untitled:definition.lol";
+        let mut server = open(uri, PROGRAM);
+
+        // "drink" on line 5 goes to its definition inside the paragraph
+        let result = at(&mut server, "textDocument/definition", uri, 5, 45);
+        assert_eq!(result.get("uri").and_then(Json::as_str), Some(uri));
+        assert_eq!(result.path(&["range", "start"]), Some(&position(4, 11)));
+        assert_eq!(result.path(&["range", "end"]), Some(&position(4, 16)));
+
+        // Plain text has no definition
+        assert_eq!(at(&mut server, "textDocument/definition", uri, 5, 28), Json::Null);
+    }
+
+    #[test]
+    fn lists_document_symbols() {
+        let uri = "untitled:symbols.lol";
+        let mut server = open(uri, PROGRAM);
+        let response = server.handle(&request(
+            3,
+            "textDocument/documentSymbol",
+            Json::object(vec![("textDocument", Json::object(vec![("uri", Json::string(uri))]))]),
+        ));
+        let symbols = response[0].get("result").and_then(Json::as_array).unwrap();
+        let summary: Vec<(&str, &str)> = symbols
+            .iter()
+            .map(|symbol| {
+                let field = |key| symbol.get(key).and_then(Json::as_str).unwrap();
+                (field("name"), field("detail"))
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            // A paragraph's detail is its first text, here a definition's
+            [("Heading", "Menu"), ("Paragraph", "Duff"), ("List", "2 items")]
+        );
+        assert_eq!(symbols[1].path(&["range", "start"]), Some(&position(3, 0)));
+        assert_eq!(symbols[1].path(&["range", "end"]), Some(&position(6, 4)));
+
+        // A request without a document is an error
+        let response = server.handle(&request(4, "textDocument/documentSymbol", Json::Null));
+        assert_eq!(response[0].path(&["error", "code"]), Some(&Json::Number(INVALID_PARAMS as f64)));
+    }
+
+    #[test]
+    fn lists_symbols_in_macro_arguments() {
+        let uri = "untitled:arguments.lol";
+        let text = "#HAI\n#HOW IZ I show #YR things\n#LEMME SEE things #MKAY\n#IF U SAY SO\n\
+                    #I IZ show #YR #MAEK LIST #GIMMEH ITEM c #MKAY #OIC #MKAY\n#KTHXBYE\n";
+        let mut server = open(uri, text);
+        let response = server.handle(&request(
+            3,
+            "textDocument/documentSymbol",
+            Json::object(vec![("textDocument", Json::object(vec![("uri", Json::string(uri))]))]),
+        ));
+        let symbols = response[0].get("result").and_then(Json::as_array).unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].get("name").and_then(Json::as_str), Some("List"));
+        assert_eq!(symbols[0].path(&["range", "start"]), Some(&position(4, 15)));
+    }
+
+    #[test]
+    fn answers_hover_after_did_open() {
+        let mut server = LanguageServer::new();
+        let initialized = server.handle(&request(1, "initialize", Json::Null));
+        assert_eq!(initialized.len(), 1);
+        assert_eq!(
+            initialized[0].path(&["result", "capabilities", "hoverProvider"]),
+            Some(&Json::Bool(true))
+        );
+
+        let uri = "untitled:hover.lol";
+        let text = "#HAI\n#I HAZ naem #IT IZ Homer #MKAY\n\
+                    #MAEK PARAGRAF\n😀 #LEMME SEE naem #MKAY\n#OIC\n#KTHXBYE\n";
+        let mut server = open(uri, text);
+        // Column 14 of line 3 is inside "naem"; the emoji counts as two units
+        let hover = at(&mut server, "textDocument/hover", uri, 3, 14);
+        assert_eq!(
+            hover.path(&["contents", "value"]).and_then(Json::as_str),
+            Some("**naem** = `Homer`\n\nDefined at line 2, column 8")
+        );
+        assert_eq!(hover.path(&["range", "start"]), Some(&position(3, 14)));
+    }
+
+    #[test]
+    fn reports_unknown_requests() {
+        let mut server = LanguageServer::new();
+        let response = server.handle(&request(7, "workspace/symbol", Json::Null));
+        assert_eq!(response[0].path(&["error", "code"]), Some(&Json::Number(METHOD_NOT_FOUND as f64)));
+        let notification = Json::object(vec![("method", Json::string("initialized"))]);
+        assert!(server.handle(&notification).is_empty());
+    }
+}
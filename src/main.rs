@@ -1,21 +1,55 @@
-mod compiler;
-mod lexer;
-mod parser;
-mod semantic;
-
-use compiler::LolCompiler;
+use lolcompiler::compiler::LolCompiler;
+use lolcompiler::output::OutputStyle;
+use lolcompiler::semantic::Stylesheet;
+use lolcompiler::{lsp, repl, theme};
 use std::env;
 use std::process;
 
+/// Command-line options for compiling a file
+struct Options {
+    input_file: String,
+    /// Variables to predefine, in the order they apply: environment
+    /// variables first, so that -D wins over them
+    defines: Vec<(String, String)>,
+    /// Emit head scripts instead of rejecting them
+    allow_scripts: bool,
+    /// Built-in theme to style the output with
+    theme: Option<String>,
+    /// --css and --css-link stylesheets, in order
+    stylesheets: Vec<Stylesheet>,
+    /// --class element=class pairs
+    classes: Vec<(String, String)>,
+    /// --pretty or --minify
+    output_style: OutputStyle,
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        eprintln!("Usage: lolcompiler <input_file.lol>");
-        process::exit(1);
+    if args.len() == 2 && args[1] == "lsp" {
+        match lsp::run() {
+            Ok(code) => process::exit(code),
+            Err(err) => {
+                eprintln!("Error: language server failed: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if args.len() == 2 && args[1] == "repl" {
+        if let Err(err) = repl::run() {
+            eprintln!("Error: REPL failed: {}", err);
+            process::exit(1);
+        }
+        return;
     }
 
-    let input_file = &args[1];
+    let options = parse_options(&args[1..], env::vars()).unwrap_or_else(|message| {
+        eprintln!("Error: {}", message);
+        usage();
+        process::exit(1);
+    });
+    let input_file = &options.input_file;
 
     if !input_file.ends_with(".lol") {
         eprintln!("Error: Input file must have .lol extension");
@@ -28,6 +62,29 @@ fn main() {
     });
 
     let mut compiler = LolCompiler::new();
+    compiler.allow_scripts(options.allow_scripts);
+    compiler.set_output_style(options.output_style);
+    if let Some(theme) = &options.theme {
+        if let Err(message) = compiler.set_theme(theme) {
+            eprintln!("Error: {}", message);
+            process::exit(1);
+        }
+    }
+    for stylesheet in options.stylesheets {
+        if let Err(message) = compiler.add_stylesheet(stylesheet) {
+            eprintln!("Error: {}", message);
+            process::exit(1);
+        }
+    }
+    for (tag, class) in &options.classes {
+        if let Err(message) = compiler.set_class(tag, class) {
+            eprintln!("Error: {}", message);
+            process::exit(1);
+        }
+    }
+    for (name, value) in &options.defines {
+        compiler.define(name, value);
+    }
     compiler.compile_file(&source, input_file);
 
     println!(
@@ -35,3 +92,222 @@ fn main() {
         input_file.replace(".lol", ".html")
     );
 }
+
+fn usage() {
+    eprintln!("Usage: lolcompiler [options] <input_file.lol>");
+    eprintln!("       lolcompiler lsp");
+    eprintln!("       lolcompiler repl");
+    eprintln!();
+    eprintln!("  -D name=value        define variable 'name' for the document");
+    eprintln!("  --env-prefix PREFIX  define a variable for every environment variable");
+    eprintln!("                       starting with PREFIX, named after the rest of it");
+    eprintln!("  --allow-scripts      emit #GIMMEH SCRIPT and #MAEK SCRIPT in the head");
+    eprintln!("  --theme NAME         style the output with a built-in theme ({})", theme::names().join(", "));
+    eprintln!("  --css FILE           put the stylesheet FILE in the head");
+    eprintln!("  --css-link ADDRESS   link the stylesheet at ADDRESS from the head");
+    eprintln!("  --class TAG=CLASS    give every TAG element (p, h1, li, ...) the class CLASS");
+    eprintln!("  --pretty             write one block element per line, indented");
+    eprintln!("  --minify             write the HTML without layout whitespace");
+    eprintln!();
+    eprintln!("Defined variables live in a scope around the document, so an #I HAZ of");
+    eprintln!("the same name in the file shadows them from that point on.");
+}
+
+/// Options from the command line `args`, taking --env-prefix variables
+/// from `environment`
+fn parse_options(
+    args: &[String],
+    environment: impl IntoIterator<Item = (String, String)>,
+) -> Result<Options, String> {
+    let mut input_file = None;
+    let mut env_prefix = None;
+    let mut defines = Vec::new();
+    let mut allow_scripts = false;
+    let mut theme = None;
+    let mut stylesheets = Vec::new();
+    let mut classes = Vec::new();
+    let mut output_style = OutputStyle::AsIs;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.starts_with("-D") {
+            let definition = match arg.strip_prefix("-D").filter(|rest| !rest.is_empty()) {
+                Some(definition) => definition,
+                None => args.next().ok_or("-D needs a name=value argument")?,
+            };
+            let (name, value) = definition
+                .split_once('=')
+                .ok_or_else(|| format!("'-D {}' is missing '=value'", definition))?;
+            check_variable_name(name)?;
+            defines.push((name.to_string(), value.to_string()));
+        } else if arg == "--env-prefix" {
+            let prefix = args.next().ok_or("--env-prefix needs a prefix argument")?;
+            env_prefix = Some(prefix.clone());
+        } else if arg == "--allow-scripts" {
+            allow_scripts = true;
+        } else if arg == "--pretty" || arg == "--minify" {
+            let style = if arg == "--pretty" { OutputStyle::Pretty } else { OutputStyle::Minified };
+            if output_style != OutputStyle::AsIs && output_style != style {
+                return Err("--pretty and --minify cannot be used together".to_string());
+            }
+            output_style = style;
+        } else if arg == "--theme" {
+            theme = Some(args.next().ok_or("--theme needs a theme name")?.clone());
+        } else if arg == "--css" {
+            let file = args.next().ok_or("--css needs a stylesheet file")?;
+            let css = std::fs::read_to_string(file)
+                .map_err(|err| format!("Cannot read stylesheet '{}': {}", file, err))?;
+            if css.to_ascii_lowercase().contains("</style") {
+                return Err(format!("Stylesheet '{}' cannot contain '</style'", file));
+            }
+            stylesheets.push(Stylesheet::Inline(css));
+        } else if arg == "--css-link" {
+            let address = args.next().ok_or("--css-link needs a stylesheet address")?;
+            stylesheets.push(Stylesheet::Link(address.clone()));
+        } else if arg == "--class" {
+            let pair = args.next().ok_or("--class needs a TAG=CLASS argument")?;
+            let (tag, class) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("'--class {}' is missing '=CLASS'", pair))?;
+            classes.push((tag.to_string(), class.to_string()));
+        } else if arg.starts_with('-') {
+            return Err(format!("Unknown option '{}'", arg));
+        } else if input_file.is_some() {
+            return Err(format!("Unexpected argument '{}'", arg));
+        } else {
+            input_file = Some(arg.clone());
+        }
+    }
+
+    if let Some(prefix) = env_prefix {
+        let mut environment: Vec<(String, String)> = environment
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix(&prefix)?.to_string(), value)))
+            .filter(|(name, _)| check_variable_name(name).is_ok())
+            .collect();
+        environment.sort();
+        environment.extend(defines);
+        defines = environment;
+    }
+
+    Ok(Options {
+        input_file: input_file.ok_or("No input file given")?,
+        defines,
+        allow_scripts,
+        theme,
+        stylesheets,
+        classes,
+        output_style,
+    })
+}
+
+/// A name that #LEMME SEE can refer to: one word, without keywords
+fn check_variable_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '#') {
+        return Err(format!("'{}' is not a valid variable name", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], environment: &[(&str, &str)]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let environment = environment
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+        parse_options(&args, environment)
+    }
+
+    fn pairs(defines: &[(String, String)]) -> Vec<(&str, &str)> {
+        defines.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect()
+    }
+
+    #[test]
+    fn parses_defines() {
+        let args = ["-D", "version=2.0", "-Dname=Homer Simpson", "-D", "empty=", "a.lol"];
+        let options = parse(&args, &[]).unwrap();
+        assert_eq!(options.input_file, "a.lol");
+        assert_eq!(
+            pairs(&options.defines),
+            [("version", "2.0"), ("name", "Homer Simpson"), ("empty", "")]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_defines() {
+        assert!(parse(&["a.lol", "-D"], &[]).is_err());
+        assert!(parse(&["-D", "version", "a.lol"], &[]).is_err());
+        assert!(parse(&["-D", "=1", "a.lol"], &[]).is_err());
+        assert!(parse(&["-D", "two words=1", "a.lol"], &[]).is_err());
+        assert!(parse(&["-D", "#MKAY=1", "a.lol"], &[]).is_err());
+    }
+
+    #[test]
+    fn takes_prefixed_environment_variables() {
+        let environment = [
+            ("LOL_VERSION", "2.0"),
+            ("PATH", "/bin"),
+            ("LOL_AUTHOR", "Lisa"),
+            ("LOL_", "no name"),
+        ];
+        let options = parse(&["--env-prefix", "LOL_", "a.lol"], &environment).unwrap();
+        assert_eq!(pairs(&options.defines), [("AUTHOR", "Lisa"), ("VERSION", "2.0")]);
+
+        // Without the option the environment is ignored
+        let options = parse(&["a.lol"], &environment).unwrap();
+        assert!(options.defines.is_empty());
+    }
+
+    #[test]
+    fn defines_win_over_the_environment() {
+        let options = parse(
+            &["-D", "VERSION=3.0", "--env-prefix", "LOL_", "a.lol"],
+            &[("LOL_VERSION", "2.0")],
+        )
+        .unwrap();
+        // Applied in order, so the -D value is the one left standing
+        assert_eq!(pairs(&options.defines), [("VERSION", "2.0"), ("VERSION", "3.0")]);
+
+        let mut compiler = LolCompiler::new();
+        for (name, value) in &options.defines {
+            compiler.define(name, value);
+        }
+        compiler.analyze("#HAI #MAEK PARAGRAF #LEMME SEE VERSION #MKAY #OIC #KTHXBYE");
+        assert!(compiler.semantic().get_output().contains("<p>3.0</p>"));
+    }
+
+    #[test]
+    fn document_definitions_shadow_defines() {
+        let options = parse(&["-D", "version=2.0", "a.lol"], &[]).unwrap();
+        let mut compiler = LolCompiler::new();
+        for (name, value) in &options.defines {
+            compiler.define(name, value);
+        }
+        compiler.analyze(
+            "#HAI\n\
+             #MAEK PARAGRAF #LEMME SEE version #MKAY #OIC\n\
+             #I HAZ version #IT IZ 3.0 #MKAY\n\
+             #MAEK PARAGRAF #LEMME SEE version #MKAY #OIC\n\
+             #KTHXBYE",
+        );
+        assert!(compiler.diagnostics().is_empty());
+        assert!(compiler.semantic().get_output().contains("<p>2.0</p>\n<p>3.0</p>"));
+    }
+
+    #[test]
+    fn parses_flags_and_rejects_unknown_ones() {
+        let args = ["--allow-scripts", "--pretty", "--theme", "dark", "a.lol"];
+        let options = parse(&args, &[]).unwrap();
+        assert!(options.allow_scripts);
+        assert_eq!(options.output_style, OutputStyle::Pretty);
+        assert_eq!(options.theme.as_deref(), Some("dark"));
+
+        assert!(parse(&["--pretty", "--minify", "a.lol"], &[]).is_err());
+        assert!(parse(&["--bogus", "a.lol"], &[]).is_err());
+        assert!(parse(&["a.lol", "b.lol"], &[]).is_err());
+        assert!(parse(&[], &[]).is_err());
+    }
+}
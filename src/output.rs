@@ -0,0 +1,356 @@
+/// How the generated HTML is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputStyle {
+    /// As the SemanticAnalyzer emits it
+    #[default]
+    AsIs,
+    /// One block element per line, indented by nesting depth
+    Pretty,
+    /// No whitespace beyond what the content needs
+    Minified,
+}
+
+/// Elements laid out as blocks: each starts its own line when pretty
+/// printing, and whitespace around them can be dropped
+const BLOCK_ELEMENTS: &[&str] = &[
+    "html", "head", "body", "title", "meta", "link", "p", "h1", "h2", "h3", "h4", "h5", "h6",
+    "ul", "ol", "li", "table", "thead", "tbody", "tr", "th", "td", "blockquote", "footer", "hr",
+    "nav",
+];
+
+/// Elements whose content is kept exactly as it is
+const RAW_ELEMENTS: &[&str] = &["pre", "style", "script"];
+
+/// Elements without a closing tag
+const VOID_ELEMENTS: &[&str] = &["meta", "link", "img", "br", "hr", "source"];
+
+/// Piece of generated HTML
+#[derive(Debug)]
+enum Html {
+    Element {
+        name: String,
+        open: String,
+        children: Vec<Html>,
+        closed: bool,
+    },
+    Text(String),
+    Comment(String),
+    /// A whole raw element, from its opening tag to its closing tag
+    Raw(String),
+}
+
+impl Html {
+    fn is_block(&self) -> bool {
+        match self {
+            Html::Element { name, .. } => BLOCK_ELEMENTS.contains(&name.as_str()),
+            Html::Comment(_) | Html::Raw(_) => true,
+            Html::Text(_) => false,
+        }
+    }
+}
+
+/// Lay out `html` in `style`. Text is only changed where whitespace does
+/// not matter: runs of it become one space, and it is dropped next to
+/// block elements. Raw elements are copied unchanged.
+pub fn restyle(html: &str, style: OutputStyle) -> String {
+    if style == OutputStyle::AsIs {
+        return html.to_string();
+    }
+    let pretty = style == OutputStyle::Pretty;
+    let mut out = String::new();
+    render_children(&parse(html), 0, pretty, &mut out);
+    out
+}
+
+/// Tree of the elements in `html`. Tags are copied as written; an element
+/// left open at the end is kept without a closing tag.
+fn parse(html: &str) -> Vec<Html> {
+    // Open elements with their children so far; the bottom one is the root
+    let mut stack: Vec<(String, String, Vec<Html>)> = vec![(String::new(), String::new(), Vec::new())];
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let (node, length) = if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(rest.len(), |end| end + 3);
+            (Some(Html::Comment(rest[..end].to_string())), end)
+        } else if let Some(name) = closing_tag_name(rest) {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            // Elements opened since the matching one are closed with it;
+            // a closing tag that matches nothing is dropped
+            if let Some(depth) = stack.iter().rposition(|(open, _, _)| *open == name) {
+                while stack.len() > depth {
+                    let (name, open, children) = stack.pop().unwrap();
+                    let closed = stack.len() == depth;
+                    stack.last_mut().unwrap().2.push(Html::Element {
+                        name,
+                        open,
+                        children,
+                        closed,
+                    });
+                }
+            }
+            (None, end)
+        } else if let Some(name) = opening_tag_name(rest) {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            let open = rest[..end].to_string();
+            if RAW_ELEMENTS.contains(&name.as_str()) {
+                let close = format!("</{}", name);
+                let end = rest.to_ascii_lowercase()[end..]
+                    .find(&close)
+                    .and_then(|start| rest[end + start..].find('>').map(|close| end + start + close + 1))
+                    .unwrap_or(rest.len());
+                (Some(Html::Raw(rest[..end].to_string())), end)
+            } else if VOID_ELEMENTS.contains(&name.as_str()) {
+                let element = Html::Element {
+                    name,
+                    open,
+                    children: Vec::new(),
+                    closed: false,
+                };
+                (Some(element), end)
+            } else {
+                stack.push((name, open, Vec::new()));
+                (None, end)
+            }
+        } else {
+            // Text runs up to the next tag; a '<' that starts no tag is text
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first..].find('<').map_or(rest.len(), |end| end + first);
+            (Some(Html::Text(rest[..end].to_string())), end)
+        };
+
+        if let Some(node) = node {
+            stack.last_mut().unwrap().2.push(node);
+        }
+        rest = &rest[length..];
+    }
+
+    // Elements never closed keep their content but get no closing tag
+    while stack.len() > 1 {
+        let (name, open, children) = stack.pop().unwrap();
+        stack.last_mut().unwrap().2.push(Html::Element {
+            name,
+            open,
+            children,
+            closed: false,
+        });
+    }
+    stack.pop().unwrap().2
+}
+
+/// Lower-case name of the tag opened at the start of `html`, if any
+fn opening_tag_name(html: &str) -> Option<String> {
+    let tag = html.strip_prefix('<')?;
+    let name: String = tag.chars().take_while(char::is_ascii_alphanumeric).collect();
+    if name.is_empty() || !tag.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(name.to_ascii_lowercase())
+}
+
+/// Lower-case name of the tag closed at the start of `html`, if any
+fn closing_tag_name(html: &str) -> Option<String> {
+    opening_tag_name(&format!("<{}", html.strip_prefix("</")?))
+}
+
+/// Block children on lines of their own and the inline content between
+/// them on one line each
+fn render_children(children: &[Html], depth: usize, pretty: bool, out: &mut String) {
+    let mut run: Vec<&Html> = Vec::new();
+    for child in children {
+        if child.is_block() {
+            render_line(&inline(&run), depth, pretty, out);
+            run.clear();
+            render_block(child, depth, pretty, out);
+        } else {
+            run.push(child);
+        }
+    }
+    render_line(&inline(&run), depth, pretty, out);
+}
+
+fn render_block(node: &Html, depth: usize, pretty: bool, out: &mut String) {
+    match node {
+        Html::Element {
+            name,
+            open,
+            children,
+            closed,
+        } => {
+            let close = if *closed { format!("</{}>", name) } else { String::new() };
+            if children.iter().any(Html::is_block) {
+                render_line(open, depth, pretty, out);
+                render_children(children, depth + 1, pretty, out);
+                render_line(&close, depth, pretty, out);
+            } else {
+                let children: Vec<&Html> = children.iter().collect();
+                render_line(&format!("{}{}{}", open, inline(&children), close), depth, pretty, out);
+            }
+        }
+        Html::Comment(html) | Html::Raw(html) => render_line(html, depth, pretty, out),
+        Html::Text(_) => render_line(&inline(&[node]), depth, pretty, out),
+    }
+}
+
+/// `nodes` as one line, with runs of whitespace made single spaces and
+/// none at either end
+fn inline(nodes: &[&Html]) -> String {
+    fn write(node: &Html, out: &mut String) {
+        match node {
+            Html::Element {
+                name,
+                open,
+                children,
+                closed,
+            } => {
+                out.push_str(open);
+                for child in children {
+                    write(child, out);
+                }
+                if *closed {
+                    out.push_str(&format!("</{}>", name));
+                }
+            }
+            Html::Text(text) => {
+                for c in text.chars() {
+                    if !c.is_whitespace() {
+                        out.push(c);
+                    } else if !out.ends_with(' ') {
+                        out.push(' ');
+                    }
+                }
+            }
+            Html::Comment(html) | Html::Raw(html) => out.push_str(html),
+        }
+    }
+
+    let mut out = String::new();
+    for node in nodes {
+        write(node, &mut out);
+    }
+    out.trim_matches(' ').to_string()
+}
+
+fn render_line(line: &str, depth: usize, pretty: bool, out: &mut String) {
+    if line.is_empty() {
+        return;
+    }
+    if pretty {
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(line);
+        out.push('\n');
+    } else {
+        out.push_str(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST11: &str = include_str!("../test/Test11.html");
+
+    #[test]
+    fn leaves_html_as_is_by_default() {
+        assert_eq!(restyle(TEST11, OutputStyle::AsIs), TEST11);
+    }
+
+    #[test]
+    fn pretty_prints_a_fixture() {
+        assert_eq!(
+            restyle(TEST11, OutputStyle::Pretty),
+            "<html>
+  <!-- This test case assess that your compiler handles numbered and nested lists. -->
+  <head>
+    <title>Test 11</title>
+  </head>
+  <ol>
+    <li>Wake up</li>
+    <li>Eat <b>lots of</b> donuts</li>
+    <li>
+      Go to work
+      <ul>
+        <li>Drive <i>slowly</i></li>
+        <li>Sleep</li>
+      </ul>
+    </li>
+  </ol>
+</html>
+"
+        );
+    }
+
+    #[test]
+    fn minifies_a_fixture() {
+        assert_eq!(
+            restyle(TEST11, OutputStyle::Minified),
+            "<html>\
+             <!-- This test case assess that your compiler handles numbered and nested lists. -->\
+             <head><title>Test 11</title></head><ol><li>Wake up</li>\
+             <li>Eat <b>lots of</b> donuts</li><li>Go to work<ul><li>Drive <i>slowly</i></li>\
+             <li>Sleep</li></ul></li></ol></html>"
+        );
+    }
+
+    #[test]
+    fn keeps_raw_elements_byte_identical() {
+        let code = "<pre><code>fn main() {\n    if a <b && c> d {}\n\n\t}</code></pre>";
+        let script = "<script>\n  if (a < b) { x = \"</p>\"; }\n</SCRIPT>";
+        let style = "<style>\n  p > b { color: red; }\n</style>";
+        let html = format!(
+            "<html>\n<head>\n{}\n</head>\n<p>x</p>\n{}\n{}\n</html>",
+            style, code, script
+        );
+
+        for layout in [OutputStyle::Pretty, OutputStyle::Minified] {
+            let out = restyle(&html, layout);
+            for raw in [code, script, style] {
+                assert!(out.contains(raw), "{:?} changed {:?}:\n{}", layout, raw, out);
+            }
+        }
+    }
+
+    #[test]
+    fn collapses_whitespace_in_text() {
+        let html = "<p>  a \n\t b  <b> c </b>  d </p>";
+        assert_eq!(restyle(html, OutputStyle::Minified), "<p>a b <b> c </b> d</p>");
+        assert_eq!(restyle(html, OutputStyle::Pretty), "<p>a b <b> c </b> d</p>\n");
+    }
+
+    #[test]
+    fn keeps_spaces_around_media_in_text() {
+        // Both are inline, so neither starts a line nor loses its spaces
+        let html = "<p>Listen <audio controls><source src=\"a.mp3\"></audio> or \
+                    <iframe src=\"v\"></iframe> now</p>";
+        assert_eq!(restyle(html, OutputStyle::Minified), html);
+        assert_eq!(restyle(html, OutputStyle::Pretty), format!("{}\n", html));
+    }
+
+    #[test]
+    fn drops_stray_closing_tags() {
+        assert_eq!(restyle("<p>a</b></p></td>", OutputStyle::Minified), "<p>a</p>");
+    }
+
+    #[test]
+    fn closes_elements_left_open_inside_a_closed_one() {
+        // The <b> is closed along with its <p>, without a tag of its own
+        let html = "<p>a <b>b</p><p>c</p>";
+        assert_eq!(restyle(html, OutputStyle::Minified), html);
+    }
+
+    #[test]
+    fn keeps_unclosed_elements_without_closing_tags() {
+        assert_eq!(
+            restyle("<html>\n<ul>\n<li>a\n<li>b", OutputStyle::Pretty),
+            "<html>\n  <ul>\n    <li>\n      a\n      <li>b\n"
+        );
+    }
+
+    #[test]
+    fn treats_unmatched_angle_brackets_and_void_elements_sensibly() {
+        let html = "<p>1 < 2 &amp; é<br>x</p>";
+        assert_eq!(restyle(html, OutputStyle::Minified), html);
+        assert_eq!(restyle("<p>tail <", OutputStyle::Minified), "<p>tail <");
+        assert_eq!(restyle("<!-- never closed", OutputStyle::Pretty), "<!-- never closed\n");
+    }
+}
@@ -1,371 +1,1370 @@
-use crate::lexer::Lexer;
-use crate::semantic::SemanticAnalyzer;
-use std::process;
+use crate::ast::{Document, LoopSource, MetadataField, Node, NodeKind, Operator};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::{Lexer, Token};
+
+/// Result of every parsing routine; the first syntax or lexical error
+/// stops the parse and is handed back to the caller.
+pub type ParseResult<T> = Result<T, Diagnostic>;
 
 /// Syntax Analyzer trait as specified in project requirements
 pub trait SyntaxAnalyzer {
-    fn parse_lolcode(&mut self);
-    fn parse_head(&mut self);
-    fn parse_title(&mut self);
-    fn parse_comment(&mut self);
-    fn parse_body(&mut self);
-    fn parse_paragraph(&mut self);
-    fn parse_inner_paragraph(&mut self);
-    fn parse_inner_text(&mut self);
-    fn parse_variable_define(&mut self);
-    fn parse_variable_use(&mut self);
-    fn parse_bold(&mut self);
-    fn parse_italics(&mut self);
-    fn parse_list(&mut self);
-    fn parse_list_items(&mut self);
-    fn parse_inner_list(&mut self);
-    fn parse_audio(&mut self);
-    fn parse_video(&mut self);
-    fn parse_newline(&mut self);
-    fn parse_text(&mut self);
+    fn parse_lolcode(&mut self) -> ParseResult<Document>;
+    fn parse_metadata(&mut self) -> ParseResult<Node>;
+    fn parse_head(&mut self) -> ParseResult<Node>;
+    fn parse_title(&mut self) -> ParseResult<Node>;
+    fn parse_stylesheet(&mut self) -> ParseResult<Node>;
+    fn parse_style(&mut self) -> ParseResult<Node>;
+    fn parse_script_link(&mut self) -> ParseResult<Node>;
+    fn parse_script(&mut self) -> ParseResult<Node>;
+    fn parse_favicon(&mut self) -> ParseResult<Node>;
+    fn parse_meta(&mut self) -> ParseResult<Node>;
+    fn parse_comment(&mut self) -> ParseResult<Node>;
+    fn parse_body(&mut self) -> ParseResult<Vec<Node>>;
+    fn parse_heading(&mut self) -> ParseResult<Node>;
+    fn parse_toc(&mut self) -> ParseResult<Node>;
+    fn parse_paragraph(&mut self) -> ParseResult<Node>;
+    fn parse_inner_paragraph(&mut self) -> ParseResult<Vec<Node>>;
+    fn parse_inner_text(&mut self) -> ParseResult<Node>;
+    fn parse_variable_define(&mut self) -> ParseResult<Node>;
+    fn parse_variable_assign(&mut self) -> ParseResult<Node>;
+    fn parse_variable_use(&mut self) -> ParseResult<Node>;
+    fn parse_expression(&mut self) -> ParseResult<Node>;
+    fn parse_macro_define(&mut self) -> ParseResult<Node>;
+    fn parse_macro_call(&mut self) -> ParseResult<Node>;
+    fn parse_include(&mut self) -> ParseResult<Node>;
+    fn parse_conditional(&mut self) -> ParseResult<Node>;
+    fn parse_branch(&mut self, opener: &Token) -> ParseResult<Node>;
+    fn parse_loop(&mut self) -> ParseResult<Node>;
+    fn parse_bold(&mut self) -> ParseResult<Node>;
+    fn parse_italics(&mut self) -> ParseResult<Node>;
+    fn parse_inline_code(&mut self) -> ParseResult<Node>;
+    fn parse_strikethrough(&mut self) -> ParseResult<Node>;
+    fn parse_underline(&mut self) -> ParseResult<Node>;
+    fn parse_subscript(&mut self) -> ParseResult<Node>;
+    fn parse_superscript(&mut self) -> ParseResult<Node>;
+    fn parse_highlight(&mut self) -> ParseResult<Node>;
+    fn parse_list(&mut self) -> ParseResult<Node>;
+    fn parse_list_items(&mut self) -> ParseResult<Vec<Node>>;
+    fn parse_inner_list(&mut self) -> ParseResult<Node>;
+    fn parse_quote(&mut self) -> ParseResult<Node>;
+    fn parse_horizontal_rule(&mut self) -> ParseResult<Node>;
+    fn parse_code_block(&mut self) -> ParseResult<Node>;
+    fn parse_table(&mut self) -> ParseResult<Node>;
+    fn parse_table_row(&mut self) -> ParseResult<Node>;
+    fn parse_table_cell(&mut self) -> ParseResult<Node>;
+    fn parse_audio(&mut self) -> ParseResult<Node>;
+    fn parse_video(&mut self) -> ParseResult<Node>;
+    fn parse_link(&mut self) -> ParseResult<Node>;
+    fn parse_image(&mut self) -> ParseResult<Node>;
+    fn parse_newline(&mut self) -> ParseResult<Node>;
+    fn parse_text(&mut self) -> ParseResult<Node>;
 }
 
 /// Parser implementation
 pub struct Parser<'a> {
     lexer: &'a mut Lexer,
-    current_token: String,
-    semantic: &'a mut SemanticAnalyzer,
+    current_token: Token,
+    /// End offset of the last token consumed, used to close node spans
+    previous_end: usize,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(lexer: &'a mut Lexer, semantic: &'a mut SemanticAnalyzer) -> Self {
+    pub fn new(lexer: &'a mut Lexer) -> Self {
         Self {
             lexer,
-            current_token: String::new(),
-            semantic,
+            current_token: Token::default(),
+            previous_end: 0,
+        }
+    }
+
+    /// Parse a piece of body content with no #HAI/#KTHXBYE around it, as
+    /// typed into the REPL. The whole input must be consumed.
+    pub fn parse_fragment(&mut self) -> ParseResult<Vec<Node>> {
+        self.next_token()?;
+        let nodes = self.parse_body()?;
+        if !self.current_token.is_eof() {
+            return Err(self.error(format!(
+                "Unexpected token '{}'",
+                self.current_token.text
+            )));
         }
+        Ok(nodes)
     }
 
-    fn next_token(&mut self) {
-        self.current_token = self.lexer.next_token();
+    /// True once every token has been read, e.g. when a construct was
+    /// left open at the end of the input
+    pub fn at_eof(&self) -> bool {
+        self.current_token.is_eof()
+    }
+
+    fn next_token(&mut self) -> ParseResult<()> {
+        self.previous_end = self.current_token.span.end;
+        self.current_token = self.lexer.next_token()?;
+        Ok(())
     }
 
     fn matches(&self, expected: &str) -> bool {
-        self.current_token.to_lowercase() == expected.to_lowercase()
+        self.current_token.text.to_lowercase() == expected.to_lowercase()
     }
 
-    fn expect(&mut self, expected: &str) {
+    fn expect(&mut self, expected: &str) -> ParseResult<()> {
         if !self.matches(expected) {
-            eprintln!(
-                "Syntax error: Expected '{}', found '{}'",
-                expected, self.current_token
-            );
-            process::exit(1);
+            return Err(self.error(format!(
+                "Expected '{}', found '{}'",
+                expected, self.current_token.text
+            )));
+        }
+        self.next_token()
+    }
+
+    /// Inline content of a formatting element up to and including the
+    /// #MKAY that closes `opener`, also allowing lists if `lists` is set.
+    /// Running into anything that is neither such content nor #MKAY means
+    /// the #MKAYs don't match up, which is reported at the element left open.
+    fn parse_formatted(&mut self, opener: &Token, what: &str, lists: bool) -> ParseResult<Vec<Node>> {
+        let mut children = Vec::new();
+        while !self.matches("#mkay") {
+            let allowed = self.at_inline() || (lists && self.at_list());
+            if self.current_token.is_eof() || (self.current_token.is_keyword() && !allowed) {
+                let found = if self.current_token.is_eof() {
+                    "the end of the file".to_string()
+                } else {
+                    format!("'{}'", self.current_token.text)
+                };
+                return Err(Diagnostic::syntax(
+                    format!(
+                        "'{}' is never closed: expected '#MKAY' before {}",
+                        opener.text, found
+                    ),
+                    opener.span,
+                ));
+            }
+            let child = if self.at_list() {
+                self.parse_list()?
+            } else {
+                self.parse_inline(what)?
+            };
+            children.push(child);
+        }
+        if children.is_empty() {
+            return Err(self.error(format!("{} cannot be empty", what)));
+        }
+        self.expect("#mkay")?;
+        Ok(children)
+    }
+
+    /// One piece of block content at the top level of the body or of a
+    /// macro definition
+    fn parse_content(&mut self) -> ParseResult<Node> {
+        if self.matches("#maek paragraf") {
+            self.parse_paragraph()
+        } else if self.matches("#maek headr") {
+            self.parse_heading()
+        } else if self.matches("#gimmeh toc") {
+            self.parse_toc()
+        } else if self.matches("#maek tabul") {
+            self.parse_table()
+        } else if self.matches("#maek kodez") {
+            self.parse_code_block()
+        } else if self.matches("#maek quote") {
+            self.parse_quote()
+        } else if self.matches("#gimmeh line") {
+            self.parse_horizontal_rule()
+        } else if self.at_formatting() {
+            self.parse_formatting()
+        } else if self.at_list() {
+            self.parse_list()
+        } else if self.matches("#gimmeh linx") {
+            self.parse_link()
+        } else if self.matches("#gimmeh newline") {
+            self.parse_newline()
+        } else if self.matches("#gimmeh vidz") {
+            self.parse_video()
+        } else if self.matches("#gimmeh soundz") {
+            self.parse_audio()
+        } else if self.matches("#gimmeh pikchur") {
+            self.parse_image()
+        } else if self.at_variable_define() {
+            self.parse_variable_define()
+        } else if self.matches("#nao") {
+            self.parse_variable_assign()
+        } else if self.matches("#how iz i") {
+            self.parse_macro_define()
+        } else if self.matches("#i iz") {
+            self.parse_macro_call()
+        } else if self.matches("#can has") {
+            self.parse_include()
+        } else if self.matches("#o rly") {
+            self.parse_conditional()
+        } else if self.matches("#im in yr") {
+            self.parse_loop()
+        } else if self.matches("#lemme see") {
+            self.parse_variable_use()
+        } else if !self.current_token.is_keyword() {
+            self.parse_text()
+        } else {
+            Err(self.unexpected(None))
+        }
+    }
+
+    /// The raw token of a style or script block, without the blank lines
+    /// and indentation around it
+    fn parse_raw_block(&mut self, what: &str) -> ParseResult<String> {
+        let raw = self.current_token.clone();
+        let text = raw.text.trim_end();
+        // Keep the indentation of the first line if it starts on a line of
+        // its own, so the block's lines stay aligned
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let text = match leading.rfind('\n') {
+            Some(newline) => &text[newline + 1..],
+            None => text.trim_start(),
+        };
+        if text.is_empty() {
+            return Err(Diagnostic::syntax(format!("{} cannot be empty", what), raw.span));
+        }
+        let text = text.to_string();
+        self.next_token()?;
+        Ok(text)
+    }
+
+    /// The #MAEK INFO field starting at the current token, if any
+    fn metadata_field(&self) -> Option<MetadataField> {
+        if self.matches("#gimmeh author") {
+            Some(MetadataField::Author)
+        } else if self.matches("#gimmeh date") {
+            Some(MetadataField::Date)
+        } else if self.matches("#gimmeh descripshun") {
+            Some(MetadataField::Description)
+        } else if self.matches("#gimmeh keywurdz") {
+            Some(MetadataField::Keywords)
+        } else if self.matches("#gimmeh tagz") {
+            Some(MetadataField::Tags)
+        } else {
+            None
+        }
+    }
+
+    /// True at #I HAZ or #I ALWAYS HAZ
+    fn at_variable_define(&self) -> bool {
+        self.matches("#i haz") || self.matches("#i always haz")
+    }
+
+    /// A variable's value up to and including its #MKAY: either one
+    /// expression or a fragment of inline content and lists
+    fn parse_value(&mut self, opener: &Token) -> ParseResult<Vec<Node>> {
+        if self.operator().is_some() {
+            let expression = self.parse_expression()?;
+            self.expect("#mkay")?;
+            Ok(vec![expression])
+        } else {
+            self.parse_formatted(opener, "Variable value", true)
+        }
+    }
+
+    /// The operator starting at the current token, if any
+    fn operator(&self) -> Option<Operator> {
+        if self.matches("#sum of") {
+            Some(Operator::Sum)
+        } else if self.matches("#diff of") {
+            Some(Operator::Diff)
+        } else if self.matches("#produkt of") {
+            Some(Operator::Produkt)
+        } else if self.matches("#smoosh") {
+            Some(Operator::Smoosh)
+        } else if self.matches("#both saem") {
+            Some(Operator::BothSaem)
+        } else {
+            None
+        }
+    }
+
+    /// A list item, or a loop generating list items
+    fn parse_list_entry(&mut self) -> ParseResult<Node> {
+        if self.matches("#im in yr") {
+            self.parse_loop_with(Self::parse_list_entry)
+        } else {
+            self.parse_inner_list()
+        }
+    }
+
+    /// A loop whose body is made of whatever `entry` parses, so loops can
+    /// produce block content as well as list items
+    fn parse_loop_with(&mut self, entry: fn(&mut Self) -> ParseResult<Node>) -> ParseResult<Node> {
+        let opener = self.current_token.clone();
+        self.expect("#im in yr")?;
+
+        let variable = self.expect_text("Loop variable")?;
+
+        let source = if self.matches("#ov") {
+            self.next_token()?;
+            let name = self.expect_text("Loop list variable")?;
+            LoopSource::Variable {
+                name: name.text,
+                name_span: name.span,
+            }
+        } else if self.matches("#frum") {
+            self.next_token()?;
+            let from = self.expect_number("Loop start")?;
+            self.expect("#til")?;
+            let to = self.expect_number("Loop end")?;
+            LoopSource::Range { from, to }
+        } else {
+            return Err(self.error(format!(
+                "Expected '#OV' or '#FRUM' after the loop variable, found '{}'",
+                self.current_token.text
+            )));
+        };
+
+        let mut body = Vec::new();
+        while !self.matches("#im outta yr") {
+            if self.current_token.is_eof() || self.matches("#kthxbye") {
+                return Err(Diagnostic::syntax(
+                    format!(
+                        "'{}' is never closed: expected '#IM OUTTA YR' before '{}'",
+                        opener.text, self.current_token.text
+                    ),
+                    opener.span,
+                ));
+            }
+            body.push(entry(self)?);
+        }
+        self.next_token()?;
+
+        Ok(Node::new(
+            NodeKind::Loop {
+                variable: variable.text,
+                variable_span: variable.span,
+                source,
+                body,
+            },
+            self.span_from(opener.span),
+        ))
+    }
+
+    /// Opening keywords of the formatting elements, which all nest the
+    /// same way
+    const FORMATTING: &'static [&'static str] = &[
+        "#gimmeh bold",
+        "#gimmeh italics",
+        "#gimmeh kode",
+        "#gimmeh strike",
+        "#gimmeh underlyne",
+        "#gimmeh subskript",
+        "#gimmeh suprskript",
+        "#gimmeh hilite",
+    ];
+
+    /// True at the start of a formatting element such as bold
+    fn at_formatting(&self) -> bool {
+        Self::FORMATTING.iter().any(|keyword| self.matches(keyword))
+    }
+
+    fn parse_formatting(&mut self) -> ParseResult<Node> {
+        if self.matches("#gimmeh bold") {
+            self.parse_bold()
+        } else if self.matches("#gimmeh italics") {
+            self.parse_italics()
+        } else if self.matches("#gimmeh kode") {
+            self.parse_inline_code()
+        } else if self.matches("#gimmeh strike") {
+            self.parse_strikethrough()
+        } else if self.matches("#gimmeh underlyne") {
+            self.parse_underline()
+        } else if self.matches("#gimmeh subskript") {
+            self.parse_subscript()
+        } else if self.matches("#gimmeh suprskript") {
+            self.parse_superscript()
+        } else {
+            self.parse_highlight()
+        }
+    }
+
+    /// A formatting element: `keyword`, inline content, #MKAY
+    fn parse_styled(
+        &mut self,
+        keyword: &str,
+        what: &str,
+        kind: fn(Vec<Node>) -> NodeKind,
+    ) -> ParseResult<Node> {
+        let opener = self.current_token.clone();
+        self.expect(keyword)?;
+        let children = self.parse_formatted(&opener, what, false)?;
+        Ok(Node::new(kind(children), self.span_from(opener.span)))
+    }
+
+    /// True at the start of inline content other than plain text
+    fn at_inline(&self) -> bool {
+        self.at_formatting() || self.matches("#lemme see") || self.matches("#gimmeh linx")
+    }
+
+    /// One piece of inline content: text, a formatting element, a variable
+    /// use or a link. `context` names the enclosing construct in error
+    /// messages.
+    fn parse_inline(&mut self, context: &str) -> ParseResult<Node> {
+        if self.at_formatting() {
+            self.parse_formatting()
+        } else if self.matches("#lemme see") {
+            self.parse_variable_use()
+        } else if self.matches("#gimmeh linx") {
+            self.parse_link()
+        } else if !self.current_token.is_keyword() {
+            self.parse_text()
+        } else {
+            Err(self.unexpected(Some(context)))
+        }
+    }
+
+    /// True at the start of either kind of list
+    fn at_list(&self) -> bool {
+        self.matches("#maek list") || self.matches("#maek numbrd list")
+    }
+
+    /// Error for a keyword that cannot appear here, pointing out a #MKAY
+    /// that has nothing left to close
+    fn unexpected(&self, context: Option<&str>) -> Diagnostic {
+        let mut message = match context {
+            Some(context) => format!("Unexpected token in {} '{}'", context, self.current_token.text),
+            None => format!("Unexpected token '{}'", self.current_token.text),
+        };
+        if self.matches("#mkay") {
+            message.push_str(": there is no open element for it to close");
+        }
+        self.error(message)
+    }
+
+    /// Syntax error located at the current token
+    fn error(&self, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::syntax(message, self.current_token.span)
+    }
+
+    /// Span from `start` up to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        Span::new(start.start, self.previous_end.max(start.end))
+    }
+
+    /// Text and variable uses, for places that cannot hold markup such as
+    /// the title. Stops at the first other keyword without consuming it.
+    fn parse_plain_text(&mut self, what: &str) -> ParseResult<Vec<Node>> {
+        let mut nodes = Vec::new();
+        loop {
+            if self.matches("#lemme see") {
+                nodes.push(self.parse_variable_use()?);
+            } else if !self.current_token.is_eof() && !self.current_token.is_keyword() {
+                nodes.push(self.parse_text()?);
+            } else {
+                break;
+            }
+        }
+        if nodes.is_empty() {
+            return Err(self.error(format!("{} cannot be empty", what)));
         }
-        self.next_token();
+        Ok(nodes)
+    }
+
+    /// Consume a TEXT token holding a whole number
+    fn expect_number(&mut self, what: &str) -> ParseResult<i64> {
+        let token = self.expect_text(what)?;
+        token.text.parse().map_err(|_| {
+            Diagnostic::syntax(
+                format!("{} must be a whole number, found '{}'", what, token.text),
+                token.span,
+            )
+        })
+    }
+
+    /// Consume the TEXT token that must follow a keyword, reporting
+    /// `what cannot be empty` if the keyword is followed by another keyword
+    fn expect_text(&mut self, what: &str) -> ParseResult<Token> {
+        if self.current_token.is_eof() || self.current_token.is_keyword() {
+            return Err(self.error(format!("{} cannot be empty", what)));
+        }
+        let token = self.current_token.clone();
+        self.next_token()?;
+        Ok(token)
     }
 }
 
 impl<'a> SyntaxAnalyzer for Parser<'a> {
-    fn parse_lolcode(&mut self) {
-        self.next_token();
+    fn parse_lolcode(&mut self) -> ParseResult<Document> {
+        self.next_token()?;
+        let start = self.current_token.span;
 
         if !self.matches("#hai") {
-            eprintln!("Syntax error: Program must start with #HAI");
-            process::exit(1);
+            return Err(self.error("Program must start with #HAI"));
         }
-        self.semantic.emit("<html>\n");
-        self.next_token();
+        self.next_token()?;
 
-        self.parse_body();
+        let body = self.parse_body()?;
 
         if !self.matches("#kthxbye") {
-            eprintln!("Syntax error: Program must end with #KTHXBYE");
-            process::exit(1);
+            return Err(self.error("Program must end with #KTHXBYE"));
         }
-        self.semantic.emit("</html>");
+        let end = self.current_token.span;
+
+        Ok(Document {
+            body,
+            span: start.to(end),
+        })
     }
 
-    fn parse_body(&mut self) {
-        // Comments can appear before head
+    fn parse_body(&mut self) -> ParseResult<Vec<Node>> {
+        let mut body = Vec::new();
+
+        // Optional metadata, after nothing but comments
         while self.matches("#obtw") {
-            self.parse_comment();
+            body.push(self.parse_comment()?);
+        }
+        if self.matches("#maek info") {
+            body.push(self.parse_metadata()?);
+        }
+
+        // Comments and variable definitions can appear before head, so the
+        // title can use the variables
+        while self.matches("#obtw") || self.at_variable_define() {
+            let node = if self.matches("#obtw") {
+                self.parse_comment()?
+            } else {
+                self.parse_variable_define()?
+            };
+            body.push(node);
         }
 
         // Optional head
         if self.matches("#maek head") {
-            self.parse_head();
+            body.push(self.parse_head()?);
         }
 
         // More comments after head
         while self.matches("#obtw") {
-            self.parse_comment();
+            body.push(self.parse_comment()?);
         }
 
         // Content list
-        while !self.matches("#kthxbye") && !self.current_token.is_empty() {
-            if self.matches("#maek paragraf") {
-                self.parse_paragraph();
-            } else if self.matches("#gimmeh bold") {
-                self.parse_bold();
-            } else if self.matches("#gimmeh italics") {
-                self.parse_italics();
-            } else if self.matches("#maek list") {
-                self.parse_list();
-            } else if self.matches("#gimmeh newline") {
-                self.parse_newline();
-            } else if self.matches("#gimmeh vidz") {
-                self.parse_video();
-            } else if self.matches("#gimmeh soundz") {
-                self.parse_audio();
-            } else if self.matches("#i haz") {
-                self.parse_variable_define();
-            } else if self.matches("#lemme see") {
-                self.parse_variable_use();
-            } else if !self.current_token.starts_with('#') {
-                self.parse_text();
+        while !self.matches("#kthxbye") && !self.current_token.is_eof() {
+            body.push(self.parse_content()?);
+        }
+
+        Ok(body)
+    }
+
+    fn parse_metadata(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek info")?;
+
+        let mut entries = Vec::new();
+        while !self.matches("#oic") && !self.current_token.is_eof() {
+            let field = self.metadata_field().ok_or_else(|| self.unexpected(Some("metadata")))?;
+            let entry_start = self.current_token.span;
+            self.next_token()?;
+            let value = self.parse_plain_text(&format!("Metadata field '{}'", field.name()))?;
+            self.expect("#mkay")?;
+            entries.push(Node::new(
+                NodeKind::MetadataEntry { field, value },
+                self.span_from(entry_start),
+            ));
+        }
+        if entries.is_empty() {
+            return Err(self.error("Metadata must have at least one field"));
+        }
+
+        self.expect("#oic")?;
+        Ok(Node::new(NodeKind::Metadata(entries), self.span_from(start)))
+    }
+
+    fn parse_head(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek head")?;
+
+        // Head elements come in any order; repeats are checked by the
+        // SemanticAnalyzer
+        let mut elements = Vec::new();
+        while !self.matches("#oic") && !self.current_token.is_eof() {
+            let element = if self.matches("#gimmeh title") {
+                self.parse_title()?
+            } else if self.matches("#gimmeh stylesheet") {
+                self.parse_stylesheet()?
+            } else if self.matches("#maek style") {
+                self.parse_style()?
+            } else if self.matches("#gimmeh script") {
+                self.parse_script_link()?
+            } else if self.matches("#maek script") {
+                self.parse_script()?
+            } else if self.matches("#gimmeh icon") {
+                self.parse_favicon()?
+            } else if self.matches("#gimmeh meta") {
+                self.parse_meta()?
             } else {
-                eprintln!("Syntax error: Unexpected token '{}'", self.current_token);
-                process::exit(1);
-            }
+                return Err(self.unexpected(Some("head")));
+            };
+            elements.push(element);
+        }
+        if self.matches("#oic")
+            && !elements.iter().any(|element| matches!(element.kind, NodeKind::Title(_)))
+        {
+            return Err(self.error("Head must have a title: expected '#GIMMEH TITLE' before '#OIC'"));
         }
+
+        self.expect("#oic")?;
+        Ok(Node::new(NodeKind::Head(elements), self.span_from(start)))
+    }
+
+    fn parse_title(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh title")?;
+        let title = self.parse_plain_text("Title")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Title(title), self.span_from(start)))
     }
 
-    fn parse_head(&mut self) {
-        self.expect("#maek head");
-        self.semantic.emit("<head>\n");
-        self.parse_title();
-        self.expect("#oic");
-        self.semantic.emit("</head>\n");
+    fn parse_stylesheet(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh stylesheet")?;
+        let address = self.expect_text("Stylesheet address")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Stylesheet(address.text), self.span_from(start)))
     }
 
-    fn parse_title(&mut self) {
-        self.expect("#gimmeh title");
-        self.semantic.emit("<title>");
+    fn parse_style(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek style")?;
+        let css = self.parse_raw_block("Style block")?;
+        self.expect("#oic")?;
+        Ok(Node::new(NodeKind::Style(css), self.span_from(start)))
+    }
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Title cannot be empty");
-            process::exit(1);
+    fn parse_script_link(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh script")?;
+        let address = self.expect_text("Script address")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::ScriptLink(address.text), self.span_from(start)))
+    }
+
+    fn parse_script(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek script")?;
+        let code = self.parse_raw_block("Script block")?;
+        self.expect("#oic")?;
+        Ok(Node::new(NodeKind::Script(code), self.span_from(start)))
+    }
+
+    fn parse_favicon(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh icon")?;
+        let address = self.expect_text("Icon address")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Favicon(address.text), self.span_from(start)))
+    }
+
+    fn parse_meta(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh meta")?;
+        let name = self.expect_text("Meta name")?;
+        if name.text.contains(char::is_whitespace) {
+            return Err(Diagnostic::syntax(
+                format!("Meta name must be a single word, found '{}'", name.text),
+                name.span,
+            ));
         }
-        self.semantic.emit(&self.current_token);
-        self.next_token();
+        self.expect("#it iz")?;
+        let content = self.parse_plain_text("Meta content")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(
+            NodeKind::Meta {
+                name: name.text,
+                content,
+            },
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_comment(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#obtw")?;
 
-        self.expect("#mkay");
-        self.semantic.emit("</title>\n");
+        let mut text = String::new();
+        if !self.current_token.is_eof() && !self.current_token.is_keyword() {
+            text = self.current_token.text.clone();
+            self.next_token()?;
+        }
+
+        self.expect("#tldr")?;
+        Ok(Node::new(NodeKind::Comment(text), self.span_from(start)))
     }
 
-    fn parse_comment(&mut self) {
-        self.expect("#obtw");
-        self.semantic.emit("<!-- ");
+    fn parse_heading(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek headr")?;
+
+        // The level is the first word of the text that follows the keyword
+        if self.current_token.is_eof() || self.current_token.is_keyword() {
+            return Err(self.error("Heading level cannot be empty"));
+        }
+        let token = self.current_token.clone();
+        let (word, rest) = token
+            .text
+            .split_once(char::is_whitespace)
+            .unwrap_or((&token.text, ""));
+        let level = match word.parse::<u8>() {
+            Ok(level) if (1..=6).contains(&level) => level,
+            _ => {
+                return Err(self.error(format!(
+                    "Heading level must be a number from 1 to 6, found '{}'",
+                    word
+                )))
+            }
+        };
+        self.next_token()?;
 
-        if !self.current_token.is_empty() && !self.current_token.starts_with('#') {
-            self.semantic.emit(&self.current_token);
-            self.next_token();
+        let mut children = Vec::new();
+        let rest = rest.trim_start();
+        if !rest.is_empty() {
+            let rest_start = token.span.end - rest.chars().count();
+            children.push(Node::new(
+                NodeKind::Text(rest.to_string()),
+                Span::new(rest_start, token.span.end),
+            ));
         }
+        while !self.matches("#mkay") && !self.current_token.is_eof() {
+            children.push(self.parse_inline("heading")?);
+        }
+        if children.is_empty() {
+            return Err(self.error("Heading text cannot be empty"));
+        }
+
+        self.expect("#mkay")?;
+        Ok(Node::new(
+            NodeKind::Heading { level, children },
+            self.span_from(start),
+        ))
+    }
 
-        self.expect("#tldr");
-        self.semantic.emit(" -->\n");
+    fn parse_toc(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh toc")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::TableOfContents, self.span_from(start)))
     }
 
-    fn parse_paragraph(&mut self) {
-        self.expect("#maek paragraf");
-        self.semantic.emit("<p>");
-        self.semantic.push_scope();
+    fn parse_paragraph(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek paragraf")?;
 
-        self.parse_inner_paragraph();
+        let children = self.parse_inner_paragraph()?;
 
         if !self.matches("#oic") {
-            eprintln!(
-                "Syntax error: Expected #OIC to close paragraph, found '{}'",
-                self.current_token
-            );
-            process::exit(1);
+            return Err(self.error(format!(
+                "Expected #OIC to close paragraph, found '{}'",
+                self.current_token.text
+            )));
         }
-        self.next_token();
+        self.next_token()?;
 
-        self.semantic.pop_scope();
-        self.semantic.emit("</p>\n");
+        Ok(Node::new(NodeKind::Paragraph(children), self.span_from(start)))
     }
 
-    fn parse_inner_paragraph(&mut self) {
-        while !self.matches("#oic") && !self.current_token.is_empty() {
-            if self.matches("#gimmeh bold") {
-                self.parse_bold();
-            } else if self.matches("#gimmeh italics") {
-                self.parse_italics();
+    fn parse_inner_paragraph(&mut self) -> ParseResult<Vec<Node>> {
+        let mut children = Vec::new();
+
+        // #GIMMEH CITE also ends the content, for block quotes
+        while !self.matches("#oic") && !self.matches("#gimmeh cite") && !self.current_token.is_eof() {
+            let node = if self.at_formatting() {
+                self.parse_formatting()?
             } else if self.matches("#gimmeh soundz") {
-                self.parse_audio();
+                self.parse_audio()?
             } else if self.matches("#gimmeh vidz") {
-                self.parse_video();
+                self.parse_video()?
+            } else if self.matches("#gimmeh pikchur") {
+                self.parse_image()?
+            } else if self.matches("#gimmeh linx") {
+                self.parse_link()?
             } else if self.matches("#gimmeh newline") {
-                self.parse_newline();
-            } else if self.matches("#maek list") {
-                self.parse_list();
-            } else if self.matches("#i haz") {
-                self.parse_variable_define();
+                self.parse_newline()?
+            } else if self.at_list() {
+                self.parse_list()?
+            } else if self.at_variable_define() {
+                self.parse_variable_define()?
+            } else if self.matches("#nao") {
+                self.parse_variable_assign()?
+            } else if self.matches("#i iz") {
+                self.parse_macro_call()?
             } else if self.matches("#lemme see") {
-                self.parse_variable_use();
-            } else if !self.current_token.starts_with('#') {
-                self.parse_text();
+                self.parse_variable_use()?
+            } else if !self.current_token.is_keyword() {
+                self.parse_inner_text()?
             } else {
-                eprintln!(
-                    "Syntax error: Unexpected token in paragraph '{}'",
-                    self.current_token
-                );
-                process::exit(1);
-            }
+                return Err(self.unexpected(Some("paragraph")));
+            };
+            children.push(node);
         }
+
+        Ok(children)
+    }
+
+    fn parse_inner_text(&mut self) -> ParseResult<Node> {
+        self.parse_text()
+    }
+
+    fn parse_variable_define(&mut self) -> ParseResult<Node> {
+        let opener = self.current_token.clone();
+        let constant = self.matches("#i always haz");
+        self.expect(if constant { "#i always haz" } else { "#i haz" })?;
+
+        let name = self.expect_text("Variable name")?;
+
+        self.expect("#it iz")?;
+
+        let value = self.parse_value(&opener)?;
+
+        Ok(Node::new(
+            NodeKind::VarDefine {
+                name: name.text,
+                name_span: name.span,
+                value,
+                constant,
+            },
+            self.span_from(opener.span),
+        ))
     }
 
-    fn parse_inner_text(&mut self) {
-        self.parse_text();
+    fn parse_variable_assign(&mut self) -> ParseResult<Node> {
+        let opener = self.current_token.clone();
+        self.expect("#nao")?;
+
+        let name = self.expect_text("Variable name")?;
+
+        self.expect("#r")?;
+
+        let value = self.parse_value(&opener)?;
+
+        Ok(Node::new(
+            NodeKind::VarAssign {
+                name: name.text,
+                name_span: name.span,
+                value,
+            },
+            self.span_from(opener.span),
+        ))
     }
 
-    fn parse_variable_define(&mut self) {
-        self.expect("#i haz");
+    fn parse_expression(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        let Some(operator) = self.operator() else {
+            if self.matches("#lemme see") {
+                return self.parse_variable_use();
+            }
+            if self.current_token.is_eof() || self.current_token.is_keyword() {
+                return Err(self.error(format!(
+                    "Expected a value or an operator, found '{}'",
+                    self.current_token.text
+                )));
+            }
+            return self.parse_text();
+        };
+        self.next_token()?;
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Variable name cannot be empty");
-            process::exit(1);
+        // SMOOSH takes any number of operands and ends with #MKAY, the
+        // others take exactly two
+        let mut operands = vec![self.parse_expression()?];
+        if operator == Operator::Smoosh {
+            while self.matches("#an") {
+                self.next_token()?;
+                operands.push(self.parse_expression()?);
+            }
+            self.expect("#mkay")?;
+        } else {
+            self.expect("#an")?;
+            operands.push(self.parse_expression()?);
         }
-        let var_name = self.current_token.clone();
-        self.next_token();
 
-        self.expect("#it iz");
+        Ok(Node::new(
+            NodeKind::Operation { operator, operands },
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_variable_use(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#lemme see")?;
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Variable value cannot be empty");
-            process::exit(1);
+        let name = self.expect_text("Variable name")?;
+
+        self.expect("#mkay")?;
+        Ok(Node::new(
+            NodeKind::VarUse {
+                name: name.text,
+                name_span: name.span,
+            },
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_macro_define(&mut self) -> ParseResult<Node> {
+        let opener = self.current_token.clone();
+        self.expect("#how iz i")?;
+
+        let name = self.expect_text("Macro name")?;
+
+        let mut params: Vec<(String, Span)> = Vec::new();
+        if self.matches("#yr") {
+            loop {
+                self.next_token()?;
+                let param = self.expect_text("Parameter name")?;
+                if params.iter().any(|(name, _)| *name == param.text) {
+                    return Err(Diagnostic::syntax(
+                        format!("Parameter '{}' is listed twice", param.text),
+                        param.span,
+                    ));
+                }
+                params.push((param.text, param.span));
+                if !self.matches("#an yr") {
+                    break;
+                }
+            }
+        }
+
+        let mut body = Vec::new();
+        while !self.matches("#if u say so") {
+            if self.current_token.is_eof() || self.matches("#kthxbye") {
+                return Err(Diagnostic::syntax(
+                    format!(
+                        "'{}' is never closed: expected '#IF U SAY SO' before '{}'",
+                        opener.text, self.current_token.text
+                    ),
+                    opener.span,
+                ));
+            }
+            body.push(self.parse_content()?);
         }
-        let var_value = self.current_token.clone();
-        self.next_token();
+        self.next_token()?;
 
-        self.expect("#mkay");
-        self.semantic.define_variable(var_name, var_value);
+        Ok(Node::new(
+            NodeKind::MacroDefine {
+                name: name.text,
+                name_span: name.span,
+                params,
+                body,
+            },
+            self.span_from(opener.span),
+        ))
     }
 
-    fn parse_variable_use(&mut self) {
-        self.expect("#lemme see");
+    fn parse_macro_call(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#i iz")?;
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Variable name cannot be empty");
-            process::exit(1);
+        let name = self.expect_text("Macro name")?;
+
+        // Each argument is inline content and lists, up to the next
+        // #AN YR or the closing #MKAY
+        let mut args = Vec::new();
+        if self.matches("#yr") {
+            loop {
+                self.next_token()?;
+                let mut arg = Vec::new();
+                while !self.matches("#an yr") && !self.matches("#mkay") {
+                    let node = if self.at_list() {
+                        self.parse_list()?
+                    } else {
+                        self.parse_inline("macro argument")?
+                    };
+                    arg.push(node);
+                }
+                if arg.is_empty() {
+                    return Err(self.error("Macro argument cannot be empty"));
+                }
+                args.push(arg);
+                if !self.matches("#an yr") {
+                    break;
+                }
+            }
         }
-        let var_name = self.current_token.clone();
-        self.next_token();
 
-        self.expect("#mkay");
-        let value = self.semantic.lookup_variable(&var_name);
-        self.semantic.emit(&value);
+        self.expect("#mkay")?;
+        Ok(Node::new(
+            NodeKind::MacroCall {
+                name: name.text,
+                name_span: name.span,
+                args,
+            },
+            self.span_from(start),
+        ))
     }
 
-    fn parse_bold(&mut self) {
-        self.expect("#gimmeh bold");
-        self.semantic.emit("<b>");
+    fn parse_include(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#can has")?;
+        let path = self.expect_text("Included file name")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Include(path.text), self.span_from(start)))
+    }
+
+    fn parse_conditional(&mut self) -> ParseResult<Node> {
+        let opener = self.current_token.clone();
+        self.expect("#o rly")?;
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Bold text cannot be empty");
-            process::exit(1);
+        let name = self.expect_text("Condition variable")?;
+        let mut value = None;
+        if self.matches("#it iz") {
+            self.next_token()?;
+            value = Some(self.expect_text("Condition value")?.text);
         }
-        self.semantic.emit(&self.current_token);
-        self.next_token();
 
-        self.expect("#mkay");
-        self.semantic.emit("</b>");
+        self.expect("#ya rly")?;
+        let mut branches = vec![self.parse_branch(&opener)?];
+        if self.matches("#no wai") {
+            self.next_token()?;
+            branches.push(self.parse_branch(&opener)?);
+        }
+        self.expect("#oic")?;
+
+        Ok(Node::new(
+            NodeKind::Conditional {
+                name: name.text,
+                name_span: name.span,
+                value,
+                branches,
+            },
+            self.span_from(opener.span),
+        ))
     }
 
-    fn parse_italics(&mut self) {
-        self.expect("#gimmeh italics");
-        self.semantic.emit("<i>");
+    /// Block content up to #NO WAI or the #OIC closing `opener`
+    fn parse_branch(&mut self, opener: &Token) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        let mut children = Vec::new();
+        while !self.matches("#no wai") && !self.matches("#oic") {
+            if self.current_token.is_eof() || self.matches("#kthxbye") {
+                return Err(Diagnostic::syntax(
+                    format!(
+                        "'{}' is never closed: expected '#OIC' before '{}'",
+                        opener.text, self.current_token.text
+                    ),
+                    opener.span,
+                ));
+            }
+            children.push(self.parse_content()?);
+        }
+        Ok(Node::new(NodeKind::Branch(children), self.span_from(start)))
+    }
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Italics text cannot be empty");
-            process::exit(1);
+    fn parse_loop(&mut self) -> ParseResult<Node> {
+        self.parse_loop_with(Self::parse_content)
+    }
+
+    fn parse_bold(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh bold", "Bold text", NodeKind::Bold)
+    }
+
+    fn parse_italics(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh italics", "Italics text", NodeKind::Italics)
+    }
+
+    fn parse_inline_code(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh kode", "Code text", NodeKind::InlineCode)
+    }
+
+    fn parse_strikethrough(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh strike", "Strikethrough text", NodeKind::Strikethrough)
+    }
+
+    fn parse_underline(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh underlyne", "Underlined text", NodeKind::Underline)
+    }
+
+    fn parse_subscript(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh subskript", "Subscript text", NodeKind::Subscript)
+    }
+
+    fn parse_superscript(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh suprskript", "Superscript text", NodeKind::Superscript)
+    }
+
+    fn parse_highlight(&mut self) -> ParseResult<Node> {
+        self.parse_styled("#gimmeh hilite", "Highlighted text", NodeKind::Highlight)
+    }
+
+    fn parse_list(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        let ordered = self.matches("#maek numbrd list");
+        if ordered {
+            self.expect("#maek numbrd list")?;
+        } else {
+            self.expect("#maek list")?;
         }
-        self.semantic.emit(&self.current_token);
-        self.next_token();
 
-        self.expect("#mkay");
-        self.semantic.emit("</i>");
+        let items = self.parse_list_items()?;
+
+        self.expect("#oic")?;
+        Ok(Node::new(
+            NodeKind::List { ordered, items },
+            self.span_from(start),
+        ))
     }
 
-    fn parse_list(&mut self) {
-        self.expect("#maek list");
-        self.semantic.emit("<ul>\n");
-        self.semantic.push_scope();
+    fn parse_list_items(&mut self) -> ParseResult<Vec<Node>> {
+        if !self.matches("#gimmeh item") && !self.matches("#im in yr") {
+            return Err(self.error("List must contain at least one item"));
+        }
+
+        let mut items = Vec::new();
+        while self.matches("#gimmeh item") || self.matches("#im in yr") {
+            items.push(self.parse_list_entry()?);
+        }
+        Ok(items)
+    }
 
-        self.parse_list_items();
+    fn parse_inner_list(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh item")?;
 
-        self.expect("#oic");
-        self.semantic.pop_scope();
-        self.semantic.emit("</ul>\n");
+        if self.matches("#mkay") || self.current_token.is_eof() {
+            return Err(self.error("List item cannot be empty"));
+        }
+
+        // Inline content, plus nested lists
+        let mut children = Vec::new();
+        while !self.matches("#mkay") && !self.current_token.is_eof() {
+            let node = if self.at_list() {
+                self.parse_list()?
+            } else {
+                self.parse_inline("list item")?
+            };
+            children.push(node);
+        }
+
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Item(children), self.span_from(start)))
     }
 
-    fn parse_list_items(&mut self) {
-        if !self.matches("#gimmeh item") {
-            eprintln!("Syntax error: List must contain at least one item");
-            process::exit(1);
+    fn parse_quote(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek quote")?;
+
+        let children = self.parse_inner_paragraph()?;
+
+        let mut citation = None;
+        if self.matches("#gimmeh cite") {
+            self.next_token()?;
+            let mut source = Vec::new();
+            while !self.matches("#mkay") && !self.current_token.is_eof() {
+                source.push(self.parse_inline("citation")?);
+            }
+            if source.is_empty() {
+                return Err(self.error("Citation cannot be empty"));
+            }
+            self.expect("#mkay")?;
+            citation = Some(source);
         }
 
-        while self.matches("#gimmeh item") {
-            self.parse_inner_list();
+        if !self.matches("#oic") {
+            return Err(self.error(format!(
+                "Expected #OIC to close quote, found '{}'",
+                self.current_token.text
+            )));
         }
+        self.next_token()?;
+
+        Ok(Node::new(
+            NodeKind::Quote { children, citation },
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_horizontal_rule(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh line")?;
+        Ok(Node::new(NodeKind::HorizontalRule, self.span_from(start)))
     }
 
-    fn parse_inner_list(&mut self) {
-        self.expect("#gimmeh item");
-        self.semantic.emit("<li>");
+    fn parse_code_block(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek kodez")?;
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: List item cannot be empty");
-            process::exit(1);
+        // The lexer hands over everything up to #OIC as one raw token:
+        // an optional language on the keyword's line, then the code
+        let raw = self.current_token.clone();
+        let (first_line, code) = raw.text.split_once('\n').unwrap_or((&raw.text, ""));
+        let mut words = first_line.split_whitespace();
+        let language = words.next().map(str::to_string);
+        if words.next().is_some() {
+            return Err(Diagnostic::syntax(
+                format!(
+                    "Code block language must be a single word, found '{}'",
+                    first_line.trim()
+                ),
+                raw.span,
+            ));
+        }
+        if let Some(language) = &language {
+            if !language
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '+' | '#' | '.'))
+            {
+                return Err(Diagnostic::syntax(
+                    format!("Invalid code block language '{}'", language),
+                    raw.span,
+                ));
+            }
         }
-        self.semantic.emit(&self.current_token);
-        self.next_token();
 
-        self.expect("#mkay");
-        self.semantic.emit("</li>\n");
+        // Drop the indentation before the closing #OIC and the line break
+        // ending the last line of code
+        let code = code.trim_end_matches([' ', '\t']);
+        let code = code.strip_suffix('\n').unwrap_or(code);
+        let code = code.strip_suffix('\r').unwrap_or(code).to_string();
+
+        self.next_token()?;
+        self.expect("#oic")?;
+        Ok(Node::new(
+            NodeKind::CodeBlock { language, code },
+            self.span_from(start),
+        ))
     }
 
-    fn parse_audio(&mut self) {
-        self.expect("#gimmeh soundz");
+    fn parse_table(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#maek tabul")?;
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Audio address cannot be empty");
-            process::exit(1);
+        let mut rows = Vec::new();
+        if self.matches("#gimmeh hedr row") {
+            rows.push(self.parse_table_row()?);
+        }
+        while self.matches("#gimmeh row") {
+            rows.push(self.parse_table_row()?);
+        }
+        if rows.is_empty() {
+            return Err(self.error("Table must contain at least one row"));
         }
 
-        self.semantic.emit(&format!(
-            "<audio controls><source src=\"{}\"></audio>\n",
-            self.current_token
-        ));
-        self.next_token();
+        // Every row must line up with the first one
+        let width = rows[0].children().len();
+        for (number, row) in rows.iter().enumerate().skip(1) {
+            let cells = row.children().len();
+            if cells != width {
+                return Err(Diagnostic::syntax(
+                    format!(
+                        "Table row {} has {} cells, but the first row has {}",
+                        number + 1,
+                        cells,
+                        width
+                    ),
+                    row.span,
+                ));
+            }
+        }
 
-        self.expect("#mkay");
+        if self.matches("#gimmeh hedr row") {
+            return Err(self.error("Only the first row of a table can be a header row"));
+        }
+        self.expect("#oic")?;
+        Ok(Node::new(NodeKind::Table(rows), self.span_from(start)))
     }
 
-    fn parse_video(&mut self) {
-        self.expect("#gimmeh vidz");
+    fn parse_table_row(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        let header = self.matches("#gimmeh hedr row");
+        if header {
+            self.expect("#gimmeh hedr row")?;
+        } else {
+            self.expect("#gimmeh row")?;
+        }
 
-        if self.current_token.is_empty() || self.current_token.starts_with('#') {
-            eprintln!("Syntax error: Video address cannot be empty");
-            process::exit(1);
+        if !self.matches("#gimmeh cell") {
+            return Err(self.error("Table row must contain at least one cell"));
+        }
+        let mut cells = Vec::new();
+        while self.matches("#gimmeh cell") {
+            cells.push(self.parse_table_cell()?);
         }
 
-        self.semantic.emit(&format!(
-            "<iframe src=\"{}\"></iframe>\n",
-            self.current_token
-        ));
-        self.next_token();
+        self.expect("#mkay")?;
+        Ok(Node::new(
+            NodeKind::Row { header, cells },
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_table_cell(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh cell")?;
+
+        // Cells may be left empty
+        let mut children = Vec::new();
+        while !self.matches("#mkay") && !self.current_token.is_eof() {
+            children.push(self.parse_inline("table cell")?);
+        }
 
-        self.expect("#mkay");
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Cell(children), self.span_from(start)))
     }
 
-    fn parse_newline(&mut self) {
-        self.expect("#gimmeh newline");
-        self.semantic.emit("<br>\n");
+    fn parse_audio(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh soundz")?;
+        let address = self.expect_text("Audio address")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Audio(address.text), self.span_from(start)))
     }
 
-    fn parse_text(&mut self) {
-        if !self.current_token.is_empty() && !self.current_token.starts_with('#') {
-            self.semantic.emit(&self.current_token);
-            self.semantic.emit(" ");
-            self.next_token();
+    fn parse_video(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh vidz")?;
+        let address = self.expect_text("Video address")?;
+        self.expect("#mkay")?;
+        Ok(Node::new(NodeKind::Video(address.text), self.span_from(start)))
+    }
+
+    fn parse_link(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh linx")?;
+        let address = self.expect_text("Link address")?;
+
+        let mut label = None;
+        if self.matches("#it iz") {
+            self.next_token()?;
+            label = Some(self.parse_plain_text("Link label")?);
         }
+
+        self.expect("#mkay")?;
+        Ok(Node::new(
+            NodeKind::Link {
+                address: address.text,
+                label,
+            },
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_image(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh pikchur")?;
+        let address = self.expect_text("Image address")?;
+
+        let mut alt = None;
+        if self.matches("#it iz") {
+            self.next_token()?;
+            alt = Some(self.parse_plain_text("Image alt text")?);
+        }
+
+        self.expect("#mkay")?;
+        Ok(Node::new(
+            NodeKind::Image {
+                address: address.text,
+                alt,
+            },
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_newline(&mut self) -> ParseResult<Node> {
+        let start = self.current_token.span;
+        self.expect("#gimmeh newline")?;
+        Ok(Node::new(NodeKind::Newline, self.span_from(start)))
+    }
+
+    fn parse_text(&mut self) -> ParseResult<Node> {
+        let text = self.expect_text("Text")?;
+        Ok(Node::new(NodeKind::Text(text.text), text.span))
     }
 }
@@ -0,0 +1,316 @@
+use crate::ast::Node;
+use crate::diagnostic::{Diagnostic, LineIndex};
+use crate::include::Includer;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::semantic::{GLOBAL_SCOPE, SemanticAnalyzer};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Enter LOLCODE body content, e.g.  #GIMMEH BOLD hi #MKAY
+Variables defined with #I HAZ stay defined for later lines; change them
+with #NAO name #R value #MKAY.
+An unfinished construct continues on the next line.
+
+Commands:
+  :tokens [fragment]  show the tokens of a fragment (default: the last one)
+  :ast [fragment]     show the parse tree of a fragment (default: the last one)
+  :scopes             show every variable in the symbol table stack
+  :reset              forget all variables
+  :help               show this message
+  :quit               leave the REPL";
+
+/// Interactive read-eval-print loop, started with `lolcompiler repl`.
+///
+/// Each fragment is lexed and parsed on its own, then rendered by one
+/// SemanticAnalyzer that lives for the whole session, so global variables
+/// carry over from line to line exactly as they would within one file.
+pub struct Repl {
+    semantic: SemanticAnalyzer,
+    /// Last fragment that was evaluated, for `:tokens` and `:ast`
+    last_fragment: String,
+}
+
+/// Run the REPL on stdin/stdout until end of input or `:quit`
+pub fn run() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout();
+    let mut repl = Repl::new();
+
+    writeln!(output, "LOLCODE REPL - type :help for commands, :quit to leave")?;
+
+    let mut pending = String::new();
+    loop {
+        write!(output, "{}", if pending.is_empty() { "lol> " } else { "...> " })?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+
+        if pending.is_empty() {
+            let command = line.trim();
+            if command.is_empty() {
+                continue;
+            }
+            if command.starts_with(':') {
+                if matches!(command, ":quit" | ":q") {
+                    return Ok(());
+                }
+                writeln!(output, "{}", repl.command(command))?;
+                continue;
+            }
+        }
+
+        // A blank line ends a multi-line fragment even if it is unfinished,
+        // so the error is shown instead of prompting forever
+        let finished = line.trim().is_empty();
+        pending.push_str(&line);
+        if let Some(reply) = repl.eval(&pending, finished) {
+            writeln!(output, "{}", reply)?;
+            pending.clear();
+        }
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            semantic: SemanticAnalyzer::new(),
+            last_fragment: String::new(),
+        }
+    }
+
+    /// Evaluate a fragment and return the generated HTML or the error.
+    /// Unless `finished` is set, returns None when the fragment stops in the
+    /// middle of a construct, meaning the caller should append the next
+    /// line and try again.
+    pub fn eval(&mut self, fragment: &str, finished: bool) -> Option<String> {
+        let mut nodes = match parse(fragment) {
+            Ok(nodes) => nodes,
+            Err(error) if !finished && is_unfinished(&error, fragment) => return None,
+            Err(error) => return Some(describe(&error, fragment)),
+        };
+
+        // Included files are relative to the working directory
+        let mut includer = Includer::new(None);
+        includer.resolve(&mut nodes);
+
+        self.last_fragment = fragment.to_string();
+        let html = self.semantic.analyze_fragment(&nodes);
+        let errors: Vec<String> = includer
+            .diagnostics()
+            .iter()
+            .chain(self.semantic.diagnostics())
+            .map(|error| describe(error, fragment))
+            .collect();
+
+        let mut reply = html.trim_end().to_string();
+        for error in errors {
+            if !reply.is_empty() {
+                reply.push('\n');
+            }
+            reply.push_str(&error);
+        }
+        Some(reply)
+    }
+
+    /// Run a `:command` and return what to print
+    pub fn command(&mut self, line: &str) -> String {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        let fragment = if argument.is_empty() {
+            self.last_fragment.clone()
+        } else {
+            argument.to_string()
+        };
+
+        match command {
+            ":tokens" => match Lexer::new(&fragment).tokenize() {
+                Ok(tokens) => tokens
+                    .iter()
+                    .map(|token| format!("{:?} @{}..{}", token.text, token.span.start, token.span.end))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                Err(error) => describe(&error, &fragment),
+            },
+            ":ast" => match parse(&fragment) {
+                Ok(nodes) => {
+                    let mut tree = String::new();
+                    for node in &nodes {
+                        node.write_tree(&mut tree, 0);
+                    }
+                    tree.trim_end().to_string()
+                }
+                Err(error) => describe(&error, &fragment),
+            },
+            ":scopes" => {
+                let mut lines = Vec::new();
+                for (depth, scope) in self.semantic.scopes().iter().enumerate() {
+                    let label = match depth {
+                        0 => "command line".to_string(),
+                        GLOBAL_SCOPE => "global".to_string(),
+                        _ => format!("scope {}", depth - GLOBAL_SCOPE),
+                    };
+                    lines.push(format!("{}:", label));
+                    let mut names: Vec<&String> = scope.keys().collect();
+                    names.sort();
+                    for name in names {
+                        let variable = &scope[name];
+                        let constant = if variable.constant { " (constant)" } else { "" };
+                        lines.push(format!("  {} = {:?}{}", name, variable.text(), constant));
+                    }
+                }
+                lines.join("\n")
+            }
+            ":reset" => {
+                self.semantic.clear();
+                self.last_fragment.clear();
+                "All variables cleared".to_string()
+            }
+            ":help" => HELP.to_string(),
+            _ => format!("Unknown command '{}', type :help for a list", command),
+        }
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse(fragment: &str) -> Result<Vec<Node>, Diagnostic> {
+    let mut lexer = Lexer::new(fragment);
+    Parser::new(&mut lexer).parse_fragment()
+}
+
+/// An error at the very end of the input, or one found after every token
+/// was read (e.g. an element never closed, reported at its opener), means
+/// the user hasn't finished typing the construct yet
+fn is_unfinished(error: &Diagnostic, fragment: &str) -> bool {
+    if error.span.start == fragment.chars().count() {
+        return true;
+    }
+    let mut lexer = Lexer::new(fragment);
+    let mut parser = Parser::new(&mut lexer);
+    parser.parse_fragment().is_err() && parser.at_eof()
+}
+
+/// Error message with its line and column inside the fragment
+fn describe(error: &Diagnostic, fragment: &str) -> String {
+    let (line, column) = LineIndex::new(fragment).line_col(error.span.start);
+    format!("{}:{}: {}", line + 1, column + 1, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_variables_across_fragments() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("#I HAZ naem #IT IZ Homer #MKAY\n", false), Some(String::new()));
+        assert_eq!(repl.eval("Hi #LEMME SEE naem #MKAY\n", false), Some("Hi Homer".to_string()));
+        assert_eq!(repl.eval("#NAO naem #R Bart #MKAY\n", false), Some(String::new()));
+        assert_eq!(repl.eval("#LEMME SEE naem #MKAY\n", false), Some("Bart".to_string()));
+    }
+
+    #[test]
+    fn continues_unfinished_fragments() {
+        let mut repl = Repl::new();
+        let mut pending = String::new();
+        for line in ["#MAEK PARAGRAF\n", "#GIMMEH BOLD hi\n", "#MKAY\n"] {
+            pending.push_str(line);
+            assert_eq!(repl.eval(&pending, false), None, "{:?} is unfinished", pending);
+        }
+        pending.push_str("#OIC\n");
+        assert_eq!(repl.eval(&pending, false), Some("<p><b>hi</b></p>".to_string()));
+
+        // Unterminated raw blocks continue too
+        assert_eq!(repl.eval("#MAEK KODEZ\n", false), None);
+        assert!(repl.eval("#MAEK KODEZ\nx\n#OIC\n", false).unwrap().contains("<code>"));
+    }
+
+    #[test]
+    fn blank_line_forces_the_error_out() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("#GIMMEH BOLD\n", false), None);
+        assert_eq!(
+            repl.eval("#GIMMEH BOLD\n\n", true),
+            Some(
+                "1:1: Syntax error: '#GIMMEH BOLD' is never closed: expected '#MKAY' before the end of the file"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn reports_errors_that_are_not_at_the_end() {
+        let mut repl = Repl::new();
+        assert_eq!(
+            repl.eval("#OIC hi\n", false),
+            Some("1:1: Syntax error: Unexpected token '#OIC'".to_string())
+        );
+        assert_eq!(
+            repl.eval("#LEMME SEE nope #MKAY\n", false),
+            Some("1:12: Static semantic error: Variable 'nope' used before definition".to_string())
+        );
+    }
+
+    #[test]
+    fn is_unfinished_only_at_the_end_of_the_input() {
+        let unfinished = |fragment: &str| match parse(fragment) {
+            Ok(_) => false,
+            Err(error) => is_unfinished(&error, fragment),
+        };
+        assert!(unfinished("#GIMMEH BOLD hi"));
+        assert!(unfinished("#MAEK LIST\n#GIMMEH ITEM a #MKAY\n"));
+        assert!(unfinished("#I HAZ a #IT IZ\n"));
+        assert!(!unfinished("hi"));
+        assert!(!unfinished("#MKAY hi"));
+        assert!(!unfinished("#MAEK PARAGRAF hi #OIC #OIC"));
+    }
+
+    #[test]
+    fn lists_scopes() {
+        let mut repl = Repl::new();
+        repl.semantic.predefine("version".to_string(), "2.0");
+        repl.eval("#I HAZ naem #IT IZ Homer #MKAY\n#I HAZ a #IT IZ 1 #MKAY\n", false);
+        assert_eq!(
+            repl.command(":scopes"),
+            "command line:\n  version = \"2.0\"\nglobal:\n  a = \"1\"\n  naem = \"Homer\""
+        );
+    }
+
+    #[test]
+    fn reset_forgets_variables() {
+        let mut repl = Repl::new();
+        repl.eval("#I HAZ naem #IT IZ Homer #MKAY\n", false);
+        assert_eq!(repl.command(":reset"), "All variables cleared");
+        assert_eq!(repl.command(":scopes"), "command line:\nglobal:");
+        assert_eq!(
+            repl.eval("#LEMME SEE naem #MKAY\n", false),
+            Some("1:12: Static semantic error: Variable 'naem' used before definition".to_string())
+        );
+        // The name can be defined again from scratch
+        assert_eq!(repl.eval("#I HAZ naem #IT IZ Bart #MKAY\n", false), Some(String::new()));
+    }
+
+    #[test]
+    fn shows_tokens_and_tree_of_the_last_fragment() {
+        let mut repl = Repl::new();
+        repl.eval("#GIMMEH BOLD hi #MKAY\n", false);
+        assert_eq!(
+            repl.command(":tokens"),
+            "\"#GIMMEH BOLD\" @0..12\n\"hi\" @13..15\n\"#MKAY\" @16..21"
+        );
+        assert!(repl.command(":ast").starts_with("Bold"));
+        assert!(repl.command(":bogus").starts_with("Unknown command ':bogus'"));
+    }
+}
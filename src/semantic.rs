@@ -1,92 +1,1382 @@
-use std::collections::HashMap;
-use std::process;
+use crate::ast::{Document, LoopSource, MetadataField, Node, NodeKind};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::expression::{self, Operand};
+use crate::image;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// A variable stored in a symbol table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    /// Content with any variable uses already replaced by their values, as
+    /// seen where the variable was defined
+    pub value: Vec<Node>,
+    /// Where the name appeared in its #I HAZ
+    pub definition: Span,
+    /// Defined with #I ALWAYS HAZ, so it can't be changed or redefined
+    pub constant: bool,
+}
+
+impl Variable {
+    /// The value's words without any markup
+    pub fn text(&self) -> String {
+        text_content(&self.value)
+    }
+}
+
+/// A macro stored in a symbol table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub params: Vec<(String, Span)>,
+    pub body: Vec<Node>,
+    /// The whole #HOW IZ I ... #IF U SAY SO
+    pub definition: Span,
+}
+
+/// How many macro expansions may be nested inside each other
+const MAX_MACRO_DEPTH: usize = 32;
+
+/// Index of the document's global scope. Below it is the scope holding
+/// variables given on the command line, so that a definition in the file
+/// shadows a command-line one of the same name.
+pub const GLOBAL_SCOPE: usize = 1;
+
+/// How many times loops may emit their body in the whole document, so that
+/// nested loops cannot multiply it
+const MAX_LOOP_ITERATIONS: u64 = 10_000;
+
+/// A definition recorded for tooling: the variable, plus the part of the
+/// source where it can be seen (from its definition to the end of its scope)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub variable: Variable,
+    pub visible: Span,
+}
+
+/// A #LEMME SEE recorded for tooling, with the definition it resolved to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+    pub resolved: Option<Variable>,
+}
+
+/// The #MAEK INFO section, with variable uses in its values resolved
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Metadata {
+    pub author: Option<String>,
+    pub date: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl Metadata {
+    /// Store the text of `field`, splitting lists at commas. Returns the
+    /// value as its variable holds it.
+    fn set(&mut self, field: MetadataField, text: String) -> String {
+        let list = |text: &str| -> Vec<String> {
+            text.split(',')
+                .map(|word| word.trim().to_string())
+                .filter(|word| !word.is_empty())
+                .collect()
+        };
+        match field {
+            MetadataField::Author => self.author = Some(text.clone()),
+            MetadataField::Date => self.date = Some(text.clone()),
+            MetadataField::Description => self.description = Some(text.clone()),
+            MetadataField::Keywords => {
+                self.keywords = list(&text);
+                return self.keywords.join(", ");
+            }
+            MetadataField::Tags => {
+                self.tags = list(&text);
+                return self.tags.join(", ");
+            }
+        }
+        text
+    }
+
+    /// True if a field gives the <meta> tag called `name`
+    fn gives(&self, name: &str) -> bool {
+        match name.to_lowercase().as_str() {
+            "author" => self.author.is_some(),
+            "date" => self.date.is_some(),
+            "description" => self.description.is_some(),
+            "keywords" => !self.keywords.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// A <meta> line for every field given, in a fixed order; one per tag
+    pub fn meta_tags(&self) -> String {
+        let mut html = String::new();
+        let fields = [
+            ("author", &self.author),
+            ("date", &self.date),
+            ("description", &self.description),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                html.push_str(&format!(
+                    "<meta name=\"{}\" content=\"{}\">\n",
+                    name,
+                    escape_html(value)
+                ));
+            }
+        }
+        if !self.keywords.is_empty() {
+            html.push_str(&format!(
+                "<meta name=\"keywords\" content=\"{}\">\n",
+                escape_html(&self.keywords.join(", "))
+            ));
+        }
+        for tag in &self.tags {
+            html.push_str(&format!(
+                "<meta property=\"article:tag\" content=\"{}\">\n",
+                escape_html(tag)
+            ));
+        }
+        html
+    }
+}
+
+/// Elements of the body that can be given a class
+pub const CLASS_ELEMENTS: &[&str] = &[
+    "p", "h1", "h2", "h3", "h4", "h5", "h6", "b", "i", "code", "s", "u", "sub", "sup", "mark",
+    "ul", "ol", "li", "a", "img", "blockquote", "footer", "cite", "hr", "pre", "table", "thead",
+    "tbody", "tr", "th", "td", "br", "audio", "iframe", "nav",
+];
+
+/// Classes to put on emitted elements, by tag name
+#[derive(Debug, Clone, Default)]
+pub struct Classes(HashMap<String, String>);
+
+impl Classes {
+    /// `<tag attributes>` with the tag's class, if it has one. `attributes`
+    /// is empty or starts with a space.
+    fn open(&self, tag: &str, attributes: &str) -> String {
+        match self.0.get(tag) {
+            Some(class) => format!("<{}{} class=\"{}\">", tag, attributes, class),
+            None => format!("<{}{}>", tag, attributes),
+        }
+    }
+
+    /// Like `open` for an element that already has a class of its own
+    fn open_with_class(&self, tag: &str, attributes: &str, class: &str) -> String {
+        match self.0.get(tag) {
+            Some(extra) => format!("<{}{} class=\"{} {}\">", tag, attributes, class, extra),
+            None => format!("<{}{} class=\"{}\">", tag, attributes, class),
+        }
+    }
+}
+
+/// A stylesheet given by the caller rather than the document
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stylesheet {
+    /// CSS put in a <style> element
+    Inline(String),
+    /// Address put in a <link rel="stylesheet">
+    Link(String),
+}
+
+impl Stylesheet {
+    fn html(&self) -> String {
+        match self {
+            Stylesheet::Inline(css) => format!("<style>\n{}\n</style>\n", css.trim_end()),
+            Stylesheet::Link(address) => format!(
+                "<link rel=\"stylesheet\" href=\"{}\">\n",
+                escape_html(address)
+            ),
+        }
+    }
+}
+
+/// A heading as listed in the table of contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadingEntry {
+    pub level: u8,
+    pub id: String,
+    pub text: String,
+}
 
 /// Semantic Analyzer - handles variable scoping and HTML generation.
-/// 
+///
 /// This analyzer performs two key functions:
 /// 1. Static scope resolution using a symbol table stack
 /// 2. HTML code generation (semantic actions)
-/// 
+///
 /// Symbol Table Stack for Static Scoping:
 /// - Each scope (global, paragraph, list) gets its own symbol table (HashMap)
+/// - Variables given on the command line live in one more table, under the
+///   global scope
 /// - Symbol tables are organized in a stack structure
 /// - When entering a new scope (e.g., paragraph), push a new table
 /// - When exiting a scope, pop the table (variables go out of scope)
 /// - Variable lookup searches from top of stack (innermost) to bottom (outermost)
 /// - This implements static (lexical) scoping as required
-/// 
+///
 /// HTML Generation:
-/// - As we parse, we emit corresponding HTML tags and text
+/// - As we walk the parse tree, we emit corresponding HTML tags and text
 /// - The output string accumulates the complete HTML document
+/// - A table of contents needs headings that come after it, so its spot
+///   in the output is remembered and filled in by a second pass once the
+///   walk has seen every heading
 pub struct SemanticAnalyzer {
     /// Stack of symbol tables - each HashMap represents one scope level
-    /// Bottom of stack = global scope, top = current innermost scope
-    scope_stack: Vec<HashMap<String, String>>,
-    
+    /// Bottom of stack = command-line variables, then the global scope,
+    /// top = current innermost scope
+    scope_stack: Vec<HashMap<String, Variable>>,
+    /// Macros defined in each scope, pushed and popped with `scope_stack`
+    macro_stack: Vec<HashMap<String, Macro>>,
+    /// Names of the macros being expanded, outermost first
+    expanding: Vec<String>,
+
     /// Accumulated HTML output as we traverse the parse tree
     output: String,
+
+    /// Static semantic errors found during the walk
+    diagnostics: Vec<Diagnostic>,
+
+    /// Every definition and use seen, for the language server
+    symbols: Vec<Symbol>,
+    references: Vec<Reference>,
+
+    /// Directory of the .lol file being compiled, for finding local images
+    source_dir: Option<PathBuf>,
+
+    /// Element ids handed out so far, to keep heading anchors unique
+    heading_ids: HashSet<String>,
+    /// Level of the previous heading, to spot skipped levels
+    last_heading_level: Option<u8>,
+
+    /// Every heading emitted so far, in document order
+    headings: Vec<HeadingEntry>,
+    /// Output offsets where a table of contents still has to be inserted
+    toc_positions: Vec<(usize, Span)>,
+    /// Nesting depth of variable values being emitted, whose text is escaped
+    in_value: usize,
+    /// Loop bodies emitted so far, counted against MAX_LOOP_ITERATIONS
+    loop_iterations: u64,
+    /// Paragraphs, headings and formatting elements being emitted, innermost
+    /// last, where macros and variables may only expand to inline content
+    inline_context: Vec<&'static str>,
+
+    /// Variables given on the command line, kept across `clear`
+    predefined: HashMap<String, Variable>,
+
+    /// The document's #MAEK INFO section
+    metadata: Metadata,
+    /// Whether the document has a #MAEK HEAD for the metadata to go in
+    has_head: bool,
+    /// Whether #GIMMEH SCRIPT and #MAEK SCRIPT may be emitted
+    allow_scripts: bool,
+    /// Built-in theme, first in the head so the document can override it
+    theme: Option<Stylesheet>,
+    /// Caller's stylesheets, last in the head so they override the document
+    stylesheets: Vec<Stylesheet>,
+    /// Classes for emitted elements
+    classes: Classes,
 }
 
 impl SemanticAnalyzer {
     pub fn new() -> Self {
         Self {
-            scope_stack: vec![HashMap::new()], // Start with global scope
+            // Start with the (empty) command-line scope and the global scope
+            scope_stack: vec![HashMap::new(), HashMap::new()],
+            macro_stack: vec![HashMap::new(), HashMap::new()],
+            expanding: Vec::new(),
             output: String::new(),
+            diagnostics: Vec::new(),
+            symbols: Vec::new(),
+            references: Vec::new(),
+            source_dir: None,
+            heading_ids: HashSet::new(),
+            last_heading_level: None,
+            headings: Vec::new(),
+            toc_positions: Vec::new(),
+            in_value: 0,
+            loop_iterations: 0,
+            inline_context: Vec::new(),
+            predefined: HashMap::new(),
+            metadata: Metadata::default(),
+            has_head: false,
+            allow_scripts: false,
+            theme: None,
+            stylesheets: Vec::new(),
+            classes: Classes::default(),
+        }
+    }
+
+    /// Define `name` as the text `value` in the scope under the global one,
+    /// for every later analysis. A #I HAZ of the same name in the file
+    /// shadows it like any inner definition shadows an outer one; uses
+    /// before that definition still see `value`, and #NAO changes it.
+    pub fn predefine(&mut self, name: String, value: &str) {
+        let variable = Variable {
+            value: vec![Node::new(NodeKind::Text(value.to_string()), Span::default())],
+            definition: Span::default(),
+            constant: false,
+        };
+        self.scope_stack[0].insert(name.clone(), variable.clone());
+        self.predefined.insert(name, variable);
+    }
+
+    /// Let head scripts through. Off by default, since a compiled page
+    /// would otherwise run whatever code its source pulls in.
+    pub fn set_allow_scripts(&mut self, allow: bool) {
+        self.allow_scripts = allow;
+    }
+
+    /// Start every head with `css`, so that the document's own styles and
+    /// those added with `add_stylesheet` take precedence
+    pub fn set_theme(&mut self, css: &str) {
+        self.theme = Some(Stylesheet::Inline(css.to_string()));
+    }
+
+    /// End every head with `stylesheet`, after the document's own styles
+    pub fn add_stylesheet(&mut self, stylesheet: Stylesheet) {
+        self.stylesheets.push(stylesheet);
+    }
+
+    /// Give every emitted `tag` element the class `class`. `tag` is one of
+    /// `CLASS_ELEMENTS`.
+    pub fn set_class(&mut self, tag: &str, class: &str) {
+        self.classes.0.insert(tag.to_string(), class.to_string());
+    }
+
+    /// Resolve relative image addresses against `dir`
+    pub fn set_source_dir(&mut self, dir: PathBuf) {
+        self.source_dir = Some(dir);
+    }
+
+    /// Walk the whole document, resolving variables and emitting HTML
+    pub fn analyze(&mut self, document: &Document) {
+        self.has_head = document
+            .body
+            .iter()
+            .any(|node| matches!(node.kind, NodeKind::Head(_)));
+        let has_metadata = document
+            .body
+            .iter()
+            .any(|node| matches!(node.kind, NodeKind::Metadata(_)));
+        self.emit("<html>\n");
+        // The caller's stylesheets need a head even if the document has
+        // none; #MAEK INFO makes one where it is
+        if !self.has_head && !has_metadata && (self.theme.is_some() || !self.stylesheets.is_empty()) {
+            self.emit_head(&[], document.span);
+        }
+        for node in &document.body {
+            self.analyze_node(node, document.span);
+        }
+        self.emit("</html>");
+        self.insert_tables_of_contents();
+    }
+
+    /// Walk loose body content against the current scopes, keeping any
+    /// global definitions for later fragments. Returns only the HTML
+    /// generated for these nodes; errors replace those of the previous call.
+    pub fn analyze_fragment(&mut self, nodes: &[Node]) -> String {
+        self.diagnostics.clear();
+        // Each fragment gets the whole loop limit, like a document
+        self.loop_iterations = 0;
+        let start = self.output.len();
+        let scope = nodes
+            .iter()
+            .map(|node| node.span)
+            .reduce(Span::to)
+            .unwrap_or_default();
+        for node in nodes {
+            self.analyze_node(node, scope);
+        }
+        self.insert_tables_of_contents();
+        self.output[start..].to_string()
+    }
+
+    /// Semantic actions for one node. `scope` is the span of the block that
+    /// owns the current symbol table, used to record where definitions are
+    /// visible.
+    fn analyze_node(&mut self, node: &Node, scope: Span) {
+        match &node.kind {
+            NodeKind::Comment(text) => {
+                self.emit("<!-- ");
+                self.emit(text);
+                self.emit(" -->\n");
+            }
+            NodeKind::Metadata(entries) => {
+                self.analyze_metadata(entries, scope);
+                // Without a head of its own the document gets one just
+                // for the metadata
+                if !self.has_head {
+                    self.emit_head(&[], scope);
+                }
+            }
+            NodeKind::Head(elements) => self.emit_head(elements, scope),
+            NodeKind::Title(title) => {
+                let title = escape_html(&self.resolve_text(title));
+                self.emit("<title>");
+                self.emit(&title);
+                self.emit("</title>\n");
+            }
+            NodeKind::Stylesheet(address) => {
+                if let Err(reason) = check_address(address) {
+                    self.diagnostics.push(Diagnostic::semantic(
+                        format!("Invalid stylesheet address '{}': {}", address, reason),
+                        node.span,
+                    ));
+                    return;
+                }
+                self.emit(&format!(
+                    "<link rel=\"stylesheet\" href=\"{}\">\n",
+                    escape_html(address)
+                ));
+            }
+            NodeKind::Style(css) => {
+                if contains_ignore_case(css, "</style") {
+                    self.diagnostics.push(Diagnostic::semantic(
+                        "Style block cannot contain '</style'",
+                        node.span,
+                    ));
+                    return;
+                }
+                self.emit(&format!("<style>\n{}\n</style>\n", css));
+            }
+            NodeKind::ScriptLink(address) => {
+                if !self.check_scripts_allowed(node.span) {
+                    return;
+                }
+                if let Err(reason) = check_address(address) {
+                    self.diagnostics.push(Diagnostic::semantic(
+                        format!("Invalid script address '{}': {}", address, reason),
+                        node.span,
+                    ));
+                    return;
+                }
+                self.emit(&format!(
+                    "<script src=\"{}\"></script>\n",
+                    escape_html(address)
+                ));
+            }
+            NodeKind::Script(code) => {
+                if !self.check_scripts_allowed(node.span) {
+                    return;
+                }
+                if contains_ignore_case(code, "</script") {
+                    self.diagnostics.push(Diagnostic::semantic(
+                        "Script block cannot contain '</script'",
+                        node.span,
+                    ));
+                    return;
+                }
+                self.emit(&format!("<script>\n{}\n</script>\n", code));
+            }
+            NodeKind::Favicon(address) => {
+                if let Err(reason) = check_address(address) {
+                    self.diagnostics.push(Diagnostic::semantic(
+                        format!("Invalid icon address '{}': {}", address, reason),
+                        node.span,
+                    ));
+                    return;
+                }
+                self.emit(&format!("<link rel=\"icon\" href=\"{}\">\n", escape_html(address)));
+            }
+            NodeKind::Meta { name, content } => {
+                let content = escape_html(&self.resolve_text(content));
+                self.emit(&format!(
+                    "<meta name=\"{}\" content=\"{}\">\n",
+                    escape_html(name),
+                    content
+                ));
+            }
+            NodeKind::Heading { level, children } => {
+                if let Some(previous) = self.last_heading_level {
+                    if *level > previous + 1 {
+                        self.diagnostics.push(Diagnostic::warning(
+                            format!(
+                                "Heading level {} follows level {}; level {} is skipped",
+                                level,
+                                previous,
+                                previous + 1
+                            ),
+                            node.span,
+                        ));
+                    }
+                }
+                self.last_heading_level = Some(*level);
+
+                let text = self.plain_text(children);
+                let id = self.unique_id(&slug(&text));
+                self.headings.push(HeadingEntry {
+                    level: *level,
+                    id: id.clone(),
+                    text,
+                });
+                self.emit_open(&format!("h{}", level), &format!(" id=\"{}\"", id));
+                self.inline_context.push("a heading");
+                self.analyze_inline(children, scope);
+                self.inline_context.pop();
+                self.emit(&format!("</h{}>\n", level));
+            }
+            NodeKind::TableOfContents => {
+                self.toc_positions.push((self.output.len(), node.span));
+            }
+            NodeKind::Paragraph(children) => {
+                self.emit_open("p", "");
+                self.push_scope();
+                self.inline_context.push("a paragraph");
+                for (i, child) in children.iter().enumerate() {
+                    self.analyze_node(child, node.span);
+                    // Text pads itself; elements are spaced like in analyze_inline
+                    let element = !matches!(child.kind, NodeKind::Text(_));
+                    if element && spaced_after(child, children.get(i + 1)) {
+                        self.emit(" ");
+                    }
+                }
+                self.inline_context.pop();
+                self.pop_scope();
+                self.emit("</p>\n");
+            }
+            NodeKind::Bold(children)
+            | NodeKind::Italics(children)
+            | NodeKind::InlineCode(children)
+            | NodeKind::Strikethrough(children)
+            | NodeKind::Underline(children)
+            | NodeKind::Subscript(children)
+            | NodeKind::Superscript(children)
+            | NodeKind::Highlight(children) => {
+                let tag = match node.kind {
+                    NodeKind::Bold(_) => "b",
+                    NodeKind::Italics(_) => "i",
+                    NodeKind::InlineCode(_) => "code",
+                    NodeKind::Strikethrough(_) => "s",
+                    NodeKind::Underline(_) => "u",
+                    NodeKind::Subscript(_) => "sub",
+                    NodeKind::Superscript(_) => "sup",
+                    _ => "mark",
+                };
+                self.emit_open(tag, "");
+                self.inline_context.push("inline formatting");
+                self.analyze_inline(children, scope);
+                self.inline_context.pop();
+                self.emit(&format!("</{}>", tag));
+            }
+            NodeKind::List { ordered, items } => {
+                let tag = if *ordered { "ol" } else { "ul" };
+                self.emit_open(tag, "");
+                self.emit("\n");
+                self.push_scope();
+                for item in items {
+                    self.analyze_node(item, node.span);
+                }
+                self.pop_scope();
+                self.emit(&format!("</{}>\n", tag));
+            }
+            NodeKind::Item(children) => {
+                self.emit_open("li", "");
+                self.analyze_inline(children, scope);
+                self.emit("</li>\n");
+            }
+            NodeKind::Link { address, label } => match link_target(address) {
+                Ok(href) => {
+                    let label = match label {
+                        Some(label) => self.resolve_text(label),
+                        None => address.clone(),
+                    };
+                    self.emit_open("a", &format!(" href=\"{}\"", escape_html(&href)));
+                    self.emit(&escape_html(&label));
+                    self.emit("</a>");
+                }
+                Err(reason) => self.diagnostics.push(Diagnostic::semantic(
+                    format!("Invalid link address '{}': {}", address, reason),
+                    node.span,
+                )),
+            },
+            NodeKind::Image { address, alt } => {
+                if let Err(reason) = check_address(address) {
+                    self.diagnostics.push(Diagnostic::semantic(
+                        format!("Invalid image address '{}': {}", address, reason),
+                        node.span,
+                    ));
+                    return;
+                }
+                if alt.is_none() {
+                    self.diagnostics.push(Diagnostic::warning(
+                        format!("Image '{}' has no alt text (add #IT IZ <description>)", address),
+                        node.span,
+                    ));
+                }
+
+                let alt = match alt {
+                    Some(alt) => self.resolve_text(alt),
+                    None => String::new(),
+                };
+                let mut attributes = format!(
+                    " src=\"{}\" alt=\"{}\"",
+                    escape_html(address),
+                    escape_html(&alt)
+                );
+                if let Some((width, height)) = self.local_image_size(address) {
+                    attributes.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+                }
+                self.emit_open("img", &attributes);
+            }
+            NodeKind::Quote { children, citation } => {
+                self.emit_open("blockquote", "");
+                self.push_scope();
+                for child in children {
+                    self.analyze_node(child, node.span);
+                }
+                if let Some(citation) = citation {
+                    self.emit_open("footer", "");
+                    self.emit_open("cite", "");
+                    self.analyze_inline(citation, node.span);
+                    self.emit("</cite></footer>");
+                }
+                self.pop_scope();
+                self.emit("</blockquote>\n");
+            }
+            NodeKind::HorizontalRule => {
+                self.emit_open("hr", "");
+                self.emit("\n");
+            }
+            NodeKind::CodeBlock { language, code } => {
+                self.emit_open("pre", "");
+                let open = match language {
+                    Some(language) => self.classes.open_with_class(
+                        "code",
+                        "",
+                        &format!("language-{}", escape_html(language)),
+                    ),
+                    None => self.classes.open("code", ""),
+                };
+                self.emit(&open);
+                self.emit(&escape_html(code));
+                self.emit("</code></pre>\n");
+            }
+            NodeKind::Table(rows) => {
+                self.emit_open("table", "");
+                self.emit("\n");
+                let mut in_body = false;
+                for row in rows {
+                    let header = matches!(row.kind, NodeKind::Row { header: true, .. });
+                    if header {
+                        self.emit_open("thead", "");
+                        self.emit("\n");
+                    } else if !in_body {
+                        self.emit_open("tbody", "");
+                        self.emit("\n");
+                        in_body = true;
+                    }
+                    self.analyze_node(row, scope);
+                    if header {
+                        self.emit("</thead>\n");
+                    }
+                }
+                if in_body {
+                    self.emit("</tbody>\n");
+                }
+                self.emit("</table>\n");
+            }
+            NodeKind::Row { header, cells } => {
+                let tag = if *header { "th" } else { "td" };
+                self.emit_open("tr", "");
+                for cell in cells {
+                    self.emit_open(tag, "");
+                    self.analyze_inline(cell.children(), scope);
+                    self.emit(&format!("</{}>", tag));
+                }
+                self.emit("</tr>\n");
+            }
+            NodeKind::Cell(children) => self.analyze_inline(children, scope),
+            NodeKind::Newline => {
+                self.emit_open("br", "");
+                self.emit("\n");
+            }
+            NodeKind::Audio(address) => {
+                self.emit_open("audio", " controls");
+                self.emit(&format!("<source src=\"{}\"></audio>\n", address));
+            }
+            NodeKind::Video(address) => {
+                self.emit_open("iframe", &format!(" src=\"{}\"", address));
+                self.emit("</iframe>\n");
+            }
+            NodeKind::VarDefine {
+                name,
+                name_span,
+                value,
+                constant,
+            } => {
+                let existing = self.scope_stack.last().and_then(|scope| scope.get(name));
+                if let Some(existing) = existing {
+                    let (kind, hint) = if existing.constant {
+                        ("Constant", "")
+                    } else {
+                        ("Variable", "; use #NAO to change its value")
+                    };
+                    self.diagnostics.push(Diagnostic::semantic(
+                        format!("{} '{}' is already defined in this scope{}", kind, name, hint),
+                        *name_span,
+                    ));
+                    return;
+                }
+
+                let value = self.evaluate_value(value);
+                let variable = Variable {
+                    value,
+                    definition: *name_span,
+                    constant: *constant,
+                };
+                self.symbols.push(Symbol {
+                    name: name.clone(),
+                    variable: variable.clone(),
+                    visible: Span::new(node.span.end, scope.end),
+                });
+                self.define_variable(name.clone(), variable);
+            }
+            NodeKind::VarAssign {
+                name,
+                name_span,
+                value,
+            } => self.assign_variable(name, *name_span, value),
+            NodeKind::VarUse { name, name_span } => {
+                if let Some(value) = self.use_variable(name, *name_span) {
+                    if let Some(context) = self.inline_context.last() {
+                        if has_block_content(&value) {
+                            self.diagnostics.push(Diagnostic::semantic(
+                                format!(
+                                    "Variable '{}' holds block content and cannot be used inside {}",
+                                    name, context
+                                ),
+                                *name_span,
+                            ));
+                            return;
+                        }
+                    }
+                    // Text from a variable is escaped, unlike literal text
+                    self.in_value += 1;
+                    self.analyze_inline(&value, scope);
+                    self.in_value -= 1;
+                }
+            }
+            NodeKind::MacroDefine {
+                name, params, body, ..
+            } => {
+                let definition = Macro {
+                    params: params.clone(),
+                    body: body.clone(),
+                    definition: node.span,
+                };
+                self.define_macro(name.clone(), definition);
+            }
+            NodeKind::MacroCall {
+                name,
+                name_span,
+                args,
+            } => self.expand_macro(name, *name_span, args, node.span),
+            // Left over only if the file could not be included, which has
+            // already been reported
+            NodeKind::Include(_) => {}
+            // Handled with the rest of its #MAEK INFO
+            NodeKind::MetadataEntry { .. } => {}
+            NodeKind::Conditional {
+                name,
+                name_span,
+                value,
+                branches,
+            } => {
+                let resolved = self.lookup_variable(name).cloned();
+                let holds = match (&resolved, value) {
+                    (Some(variable), Some(value)) => variable.text() == *value,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                self.references.push(Reference {
+                    name: name.clone(),
+                    span: *name_span,
+                    resolved,
+                });
+
+                // Only the chosen branch is analyzed, in a scope of its own
+                let branch = if holds { branches.first() } else { branches.get(1) };
+                if let Some(branch) = branch {
+                    self.push_scope();
+                    for child in branch.children() {
+                        self.analyze_node(child, branch.span);
+                    }
+                    self.pop_scope();
+                }
+            }
+            NodeKind::Loop {
+                variable,
+                variable_span,
+                source,
+                body,
+            } => {
+                let values: Vec<String> = match source {
+                    LoopSource::Variable { name, name_span } => {
+                        let Some(value) = self.use_variable(name, *name_span) else {
+                            return;
+                        };
+                        text_content(&value)
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|word| !word.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    }
+                    LoopSource::Range { from, to } => {
+                        if from.abs_diff(*to) >= MAX_LOOP_ITERATIONS {
+                            self.diagnostics.push(Diagnostic::semantic(
+                                format!(
+                                    "Loop from {} to {} runs more than {} times",
+                                    from, to, MAX_LOOP_ITERATIONS
+                                ),
+                                node.span,
+                            ));
+                            return;
+                        }
+                        if from <= to {
+                            (*from..=*to).map(|n| n.to_string()).collect()
+                        } else {
+                            (*to..=*from).rev().map(|n| n.to_string()).collect()
+                        }
+                    }
+                };
+
+                // Each pass gets a fresh scope holding the loop variable, so
+                // nothing defined in the body leaks into the next pass
+                for value in values {
+                    // Past the limit an enclosing loop goes on to its next
+                    // pass, so the error is only reported the first time
+                    if self.loop_iterations >= MAX_LOOP_ITERATIONS {
+                        if self.loop_iterations == MAX_LOOP_ITERATIONS {
+                            self.diagnostics.push(Diagnostic::semantic(
+                                format!(
+                                    "Loops run more than {} times in total",
+                                    MAX_LOOP_ITERATIONS
+                                ),
+                                node.span,
+                            ));
+                            self.loop_iterations += 1;
+                        }
+                        return;
+                    }
+                    self.loop_iterations += 1;
+                    self.push_scope();
+                    let variable_value = Variable {
+                        value: vec![Node::new(NodeKind::Text(value), *variable_span)],
+                        definition: *variable_span,
+                        constant: false,
+                    };
+                    self.define_variable(variable.clone(), variable_value);
+                    for child in body {
+                        self.analyze_node(child, node.span);
+                    }
+                    self.pop_scope();
+                }
+            }
+            NodeKind::Operation { .. } => {
+                if let Some(result) = self.evaluate(node) {
+                    self.emit(&escape_html(&result.text));
+                }
+            }
+            NodeKind::Branch(children) => {
+                for child in children {
+                    self.analyze_node(child, node.span);
+                }
+            }
+            NodeKind::Text(text) => {
+                self.emit_text(text);
+                self.emit(" ");
+            }
+        }
+    }
+
+    /// The <head>: the theme, the document's head `elements`, the metadata
+    /// and the caller's stylesheets, in that order
+    fn emit_head(&mut self, elements: &[Node], scope: Span) {
+        self.emit("<head>\n");
+        if let Some(theme) = &self.theme {
+            let html = theme.html();
+            self.emit(&html);
+        }
+        let mut seen = HashSet::new();
+        for element in elements {
+            if let Some(message) = self.repeated_head_element(element, &mut seen) {
+                self.diagnostics.push(Diagnostic::semantic(message, element.span));
+                continue;
+            }
+            self.analyze_node(element, scope);
+        }
+        let meta_tags = self.metadata.meta_tags();
+        self.emit(&meta_tags);
+        let stylesheets: String = self.stylesheets.iter().map(Stylesheet::html).collect();
+        self.emit(&stylesheets);
+        self.emit("</head>\n");
+    }
+
+    /// Why a head element may not be added to the elements `seen` so far,
+    /// if it repeats one of them
+    fn repeated_head_element(&self, element: &Node, seen: &mut HashSet<String>) -> Option<String> {
+        let (key, message) = match &element.kind {
+            NodeKind::Title(_) => ("title".to_string(), "Head has more than one title".to_string()),
+            NodeKind::Favicon(_) => ("icon".to_string(), "Head has more than one icon".to_string()),
+            NodeKind::Stylesheet(address) => (
+                format!("stylesheet {}", address),
+                format!("Stylesheet '{}' is linked more than once", address),
+            ),
+            NodeKind::ScriptLink(address) => (
+                format!("script {}", address),
+                format!("Script '{}' is linked more than once", address),
+            ),
+            NodeKind::Meta { name, .. } => {
+                if self.metadata.gives(name) {
+                    return Some(format!("Meta '{}' is already given by #MAEK INFO", name));
+                }
+                (
+                    format!("meta {}", name.to_lowercase()),
+                    format!("Meta '{}' is given more than once", name),
+                )
+            }
+            _ => return None,
+        };
+        if seen.insert(key) {
+            None
+        } else {
+            Some(message)
+        }
+    }
+
+    /// Scripts run in the reader's browser, so they are only emitted when
+    /// asked for. Reports the script at `span` otherwise.
+    fn check_scripts_allowed(&mut self, span: Span) -> bool {
+        if !self.allow_scripts {
+            self.diagnostics.push(Diagnostic::semantic(
+                "Scripts are disabled; compile with --allow-scripts to include them",
+                span,
+            ));
+        }
+        self.allow_scripts
+    }
+
+    /// Record the fields of #MAEK INFO and define a constant named after
+    /// each one, holding its value
+    fn analyze_metadata(&mut self, entries: &[Node], scope: Span) {
+        let mut seen = Vec::new();
+        for entry in entries {
+            let NodeKind::MetadataEntry { field, value } = &entry.kind else {
+                continue;
+            };
+            if seen.contains(field) {
+                self.diagnostics.push(Diagnostic::semantic(
+                    format!("Metadata field '{}' is given more than once", field.name()),
+                    entry.span,
+                ));
+                continue;
+            }
+            seen.push(*field);
+
+            let text = self.resolve_text(value);
+            let text = self.metadata.set(*field, text);
+            let variable = Variable {
+                value: vec![Node::new(NodeKind::Text(text), entry.span)],
+                definition: entry.span,
+                constant: true,
+            };
+            self.symbols.push(Symbol {
+                name: field.variable(),
+                variable: variable.clone(),
+                visible: Span::new(entry.span.end, scope.end),
+            });
+            self.define_variable(field.variable(), variable);
         }
     }
 
+    /// What a variable defined or assigned with `value` holds. An
+    /// expression is computed once, here, and its result is the value.
+    fn evaluate_value(&mut self, value: &[Node]) -> Vec<Node> {
+        match value {
+            [operation @ Node {
+                kind: NodeKind::Operation { .. },
+                ..
+            }] => match self.evaluate(operation) {
+                Some(result) => vec![Node::new(NodeKind::Text(result.text), operation.span)],
+                None => Vec::new(),
+            },
+            _ => self.expand(value),
+        }
+    }
+
+    /// #NAO: change the closest definition of `name`, which may be in an
+    /// outer scope. Constants can't be changed.
+    fn assign_variable(&mut self, name: &str, name_span: Span, value: &[Node]) {
+        let resolved = self.lookup_variable(name).cloned();
+        self.references.push(Reference {
+            name: name.to_string(),
+            span: name_span,
+            resolved: resolved.clone(),
+        });
+        match resolved {
+            None => {
+                self.diagnostics.push(Diagnostic::semantic(
+                    format!("Variable '{}' assigned before definition", name),
+                    name_span,
+                ));
+                return;
+            }
+            Some(variable) if variable.constant => {
+                self.diagnostics.push(Diagnostic::semantic(
+                    format!("Cannot change constant '{}'", name),
+                    name_span,
+                ));
+                return;
+            }
+            Some(_) => {}
+        }
+
+        // The value is computed before the old one is replaced, so it can
+        // refer to the variable itself
+        let value = self.evaluate_value(value);
+        if let Some(variable) = self
+            .scope_stack
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+        {
+            variable.value = value;
+        }
+    }
+
+    /// Compute the value of an expression operand. Type errors are
+    /// reported at the offending operand and give None.
+    fn evaluate(&mut self, node: &Node) -> Option<Operand> {
+        match &node.kind {
+            NodeKind::Operation { operator, operands } => {
+                let mut values = Vec::new();
+                for operand in operands {
+                    values.push(self.evaluate(operand)?);
+                }
+                match expression::apply(*operator, &values) {
+                    Ok(result) => Some(Operand::from(result)),
+                    Err(error) => {
+                        let span = error.operand.map_or(node.span, |operand| operands[operand].span);
+                        self.diagnostics.push(Diagnostic::semantic(error.message, span));
+                        None
+                    }
+                }
+            }
+            NodeKind::VarUse { name, name_span } => {
+                let value = self.use_variable(name, *name_span)?;
+                Some(Operand::from_text(&text_content(&value)))
+            }
+            NodeKind::Text(text) => Some(Operand::from_text(text)),
+            _ => None,
+        }
+    }
+
+    /// Emit the body of macro `name` with its parameters bound to `args`.
+    ///
+    /// The arguments are evaluated where the macro is used, but the body
+    /// only sees the scopes that were visible where the macro was defined
+    /// (static scoping), plus a fresh scope holding the parameters.
+    fn expand_macro(&mut self, name: &str, name_span: Span, args: &[Vec<Node>], span: Span) {
+        let Some((depth, definition)) = self.lookup_macro(name) else {
+            self.diagnostics.push(Diagnostic::semantic(
+                format!("Macro '{}' used before definition", name),
+                name_span,
+            ));
+            return;
+        };
+        let definition = definition.clone();
+
+        if self.expanding.iter().any(|expanding| expanding == name) {
+            let mut chain = self.expanding.clone();
+            chain.push(name.to_string());
+            self.diagnostics.push(Diagnostic::semantic(
+                format!("Macro '{}' expands itself: {}", name, chain.join(" -> ")),
+                span,
+            ));
+            return;
+        }
+        if self.expanding.len() >= MAX_MACRO_DEPTH {
+            self.diagnostics.push(Diagnostic::semantic(
+                format!(
+                    "Macro '{}' is nested more than {} expansions deep",
+                    name, MAX_MACRO_DEPTH
+                ),
+                span,
+            ));
+            return;
+        }
+        if args.len() != definition.params.len() {
+            self.diagnostics.push(Diagnostic::semantic(
+                format!(
+                    "Macro '{}' takes {} argument(s) but {} were given",
+                    name,
+                    definition.params.len(),
+                    args.len()
+                ),
+                span,
+            ));
+            return;
+        }
+        if let Some(context) = self.inline_context.last() {
+            if has_block_content(&definition.body) {
+                self.diagnostics.push(Diagnostic::semantic(
+                    format!(
+                        "Macro '{}' has block content and cannot be used inside {}",
+                        name, context
+                    ),
+                    span,
+                ));
+                return;
+            }
+        }
+
+        let values: Vec<Vec<Node>> = args.iter().map(|arg| self.expand(arg)).collect();
+
+        let caller_scopes = self.scope_stack.split_off(depth + 1);
+        let caller_macros = self.macro_stack.split_off(depth + 1);
+        self.push_scope();
+        for ((param, param_span), value) in definition.params.iter().zip(values) {
+            let variable = Variable {
+                value,
+                definition: *param_span,
+                constant: false,
+            };
+            self.define_variable(param.clone(), variable);
+        }
+
+        self.expanding.push(name.to_string());
+        for node in &definition.body {
+            self.analyze_node(node, definition.definition);
+        }
+        self.expanding.pop();
+
+        self.pop_scope();
+        self.scope_stack.extend(caller_scopes);
+        self.macro_stack.extend(caller_macros);
+    }
+
+    /// Second pass: put a nested list of links to all headings wherever
+    /// #GIMMEH TOC appeared. Later positions go first so earlier offsets
+    /// stay valid.
+    fn insert_tables_of_contents(&mut self) {
+        let positions = std::mem::take(&mut self.toc_positions);
+        if positions.is_empty() {
+            return;
+        }
+        if self.headings.is_empty() {
+            for (_, span) in &positions {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Table of contents is empty: the document has no headings",
+                    *span,
+                ));
+            }
+            return;
+        }
+
+        let toc = table_of_contents(&self.headings, &self.classes);
+        for (position, _) in positions.iter().rev() {
+            self.output.insert_str(*position, &toc);
+        }
+    }
+
+    /// Inline content of an element that closes right after it (list
+    /// items, headings). Words are separated by a space, but the last text
+    /// is not padded before the closing tag.
+    fn analyze_inline(&mut self, children: &[Node], scope: Span) {
+        for (i, child) in children.iter().enumerate() {
+            match &child.kind {
+                NodeKind::Text(text) => {
+                    self.emit_text(text);
+                    if i + 1 < children.len() {
+                        self.emit(" ");
+                    }
+                }
+                _ => {
+                    self.analyze_node(child, scope);
+                    if spaced_after(child, children.get(i + 1)) {
+                        self.emit(" ");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Value of a variable use, recorded as a reference. Reports an error
+    /// and returns None if no visible definition exists.
+    fn use_variable(&mut self, name: &str, name_span: Span) -> Option<Vec<Node>> {
+        let resolved = self.lookup_variable(name).cloned();
+        if resolved.is_none() {
+            self.diagnostics.push(Diagnostic::semantic(
+                format!("Variable '{}' used before definition", name),
+                name_span,
+            ));
+        }
+        let value = resolved.as_ref().map(|variable| variable.value.clone());
+        self.references.push(Reference {
+            name: name.to_string(),
+            span: name_span,
+            resolved,
+        });
+        value
+    }
+
+    /// Text and variable uses of a title, label or alt text joined into one
+    /// string, reporting undefined variables
+    fn resolve_text(&mut self, nodes: &[Node]) -> String {
+        text_content(&self.expand(nodes))
+    }
+
+    /// Copy of `nodes` with every variable use replaced by the variable's
+    /// value, so a definition captures the variables visible where it is
+    fn expand(&mut self, nodes: &[Node]) -> Vec<Node> {
+        let mut expanded = Vec::new();
+        for node in nodes {
+            match &node.kind {
+                NodeKind::VarUse { name, name_span } => {
+                    if let Some(value) = self.use_variable(name, *name_span) {
+                        expanded.extend(value);
+                    }
+                }
+                _ => {
+                    let mut node = node.clone();
+                    for children in node.child_lists_mut() {
+                        *children = self.expand(children);
+                    }
+                    expanded.push(node);
+                }
+            }
+        }
+        expanded
+    }
+
+    /// Literal text as is, text from a variable's value escaped
+    fn emit_text(&mut self, text: &str) {
+        if self.in_value > 0 {
+            let escaped = escape_html(text);
+            self.emit(&escaped);
+        } else {
+            self.emit(text);
+        }
+    }
+
+    /// The words of some inline content without any markup, with
+    /// variables replaced by their values
+    fn plain_text(&self, nodes: &[Node]) -> String {
+        let mut words = Vec::new();
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Text(text) => words.push(text.clone()),
+                NodeKind::Link {
+                    address,
+                    label: None,
+                } => words.push(address.clone()),
+                NodeKind::VarUse { name, .. } => {
+                    if let Some(variable) = self.lookup_variable(name) {
+                        words.push(variable.text());
+                    }
+                }
+                _ => words.push(self.plain_text(node.children())),
+            }
+        }
+        words.retain(|word| !word.is_empty());
+        words.join(" ")
+    }
+
+    /// `base`, or `base-2`, `base-3`, ... if an earlier element took it
+    fn unique_id(&mut self, base: &str) -> String {
+        let mut id = base.to_string();
+        let mut n = 2;
+        while self.heading_ids.contains(&id) {
+            id = format!("{}-{}", base, n);
+            n += 1;
+        }
+        self.heading_ids.insert(id.clone());
+        id
+    }
+
+    /// Pixel size of a relative image address that exists on disk
+    fn local_image_size(&self, address: &str) -> Option<(u32, u32)> {
+        if has_scheme(address) {
+            return None;
+        }
+        let path = address.split('?').next().unwrap_or(address);
+        let dir = self.source_dir.clone().unwrap_or_default();
+        image::dimensions(&dir.join(path))
+    }
+
     /// Push a new scope onto the stack
     pub fn push_scope(&mut self) {
         self.scope_stack.push(HashMap::new());
+        self.macro_stack.push(HashMap::new());
     }
 
     /// Pop the current scope from the stack
     pub fn pop_scope(&mut self) {
-        if self.scope_stack.len() > 1 {
+        if self.scope_stack.len() > GLOBAL_SCOPE + 1 {
             self.scope_stack.pop();
+            self.macro_stack.pop();
         }
     }
 
     /// Define a variable in the current scope
-    pub fn define_variable(&mut self, name: String, value: String) {
+    pub fn define_variable(&mut self, name: String, variable: Variable) {
         if let Some(current_scope) = self.scope_stack.last_mut() {
-            current_scope.insert(name, value);
+            current_scope.insert(name, variable);
         }
     }
 
-    /// Look up a variable value using static (lexical) scoping.
-    /// 
+    /// Look up a variable using static (lexical) scoping.
+    ///
     /// Static Scoping Algorithm:
     /// 1. Start at the top of the scope stack (current/innermost scope)
     /// 2. Check if variable exists in current scope's symbol table
-    /// 3. If found, return its value
+    /// 3. If found, return it
     /// 4. If not found, move to next outer scope (down the stack)
     /// 5. Repeat until variable found or all scopes exhausted
-    /// 6. If never found, this is a static semantic error
-    /// 
+    /// 6. If never found, this is a static semantic error (reported by the caller)
+    ///
     /// Example with nested scopes:
     ///   Global scope: { myname: "Josh" }
     ///   Paragraph scope: { myname: "Jon" }
-    ///   
+    ///
     ///   Looking up "myname" from paragraph will find "Jon"
     ///   Looking up "myname" after paragraph finds "Josh"
-    ///   
+    ///
     /// This is "static" because scope is determined by program structure,
     /// not by runtime call stack (as in dynamic scoping).
-    pub fn lookup_variable(&self, name: &str) -> String {
+    pub fn lookup_variable(&self, name: &str) -> Option<&Variable> {
         // Search from innermost to outermost scope
         // .iter().rev() traverses stack from top to bottom
-        for scope in self.scope_stack.iter().rev() {
-            if let Some(value) = scope.get(name) {
-                return value.clone();  // Variable found - return its value
-            }
+        self.scope_stack
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+    }
+
+    /// Define a macro in the current scope
+    pub fn define_macro(&mut self, name: String, definition: Macro) {
+        if let Some(current_scope) = self.macro_stack.last_mut() {
+            current_scope.insert(name, definition);
         }
+    }
 
-        // Variable not found in any scope - this is a static semantic error
-        // In a real compiler, this would be caught during a separate semantic analysis pass
-        // Here we catch it during parsing and immediately report the error
-        eprintln!("Static semantic error: Variable '{}' used before definition", name);
-        process::exit(1);
+    /// Look up a macro the same way as a variable. Also returns the depth
+    /// of the scope that defines it.
+    pub fn lookup_macro(&self, name: &str) -> Option<(usize, &Macro)> {
+        self.macro_stack
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| scope.get(name).map(|definition| (depth, definition)))
+    }
+
+    /// The symbol tables, outermost (command line, then global) first
+    pub fn scopes(&self) -> &[HashMap<String, Variable>] {
+        &self.scope_stack
     }
 
     /// Emit HTML code to the output
@@ -94,15 +1384,264 @@ impl SemanticAnalyzer {
         self.output.push_str(html);
     }
 
+    /// Emit the opening tag of a `tag` element, with its configured class
+    fn emit_open(&mut self, tag: &str, attributes: &str) {
+        let html = self.classes.open(tag, attributes);
+        self.emit(&html);
+    }
+
     /// Get the generated HTML output
     pub fn get_output(&self) -> &str {
         &self.output
     }
 
+    /// The #MAEK INFO section seen by the last `analyze`
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Static semantic errors found by the last `analyze`
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Definitions seen by the last `analyze`, in source order
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Variable uses seen by the last `analyze`, in source order
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
     /// Clear the output (for testing or reset)
     pub fn clear(&mut self) {
         self.output.clear();
-        self.scope_stack.clear();
-        self.scope_stack.push(HashMap::new());
+        self.scope_stack = vec![self.predefined.clone(), HashMap::new()];
+        self.macro_stack = vec![HashMap::new(), HashMap::new()];
+        self.expanding.clear();
+        self.diagnostics.clear();
+        self.symbols.clear();
+        self.references.clear();
+        self.heading_ids.clear();
+        self.last_heading_level = None;
+        self.headings.clear();
+        self.toc_positions.clear();
+        self.in_value = 0;
+        self.loop_iterations = 0;
+        self.inline_context.clear();
+        self.metadata = Metadata::default();
+        self.has_head = false;
+    }
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// True if `nodes` hold anything that cannot go inside a paragraph,
+/// looking into conditionals and loops
+fn has_block_content(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match &node.kind {
+        NodeKind::Paragraph(_)
+        | NodeKind::Heading { .. }
+        | NodeKind::List { .. }
+        | NodeKind::TableOfContents
+        | NodeKind::Quote { .. }
+        | NodeKind::HorizontalRule
+        | NodeKind::CodeBlock { .. }
+        | NodeKind::Table(_) => true,
+        NodeKind::Conditional { .. } | NodeKind::Branch(_) | NodeKind::Loop { .. } => {
+            has_block_content(node.children())
+        }
+        _ => false,
+    })
+}
+
+/// True if a space goes between an inline element and `next`, as the
+/// whitespace between them in the source is not kept. Definitions and
+/// comments print nothing, and punctuation stays against the element.
+fn spaced_after(element: &Node, next: Option<&Node>) -> bool {
+    let silent = matches!(
+        element.kind,
+        NodeKind::VarDefine { .. }
+            | NodeKind::VarAssign { .. }
+            | NodeKind::MacroDefine { .. }
+            | NodeKind::Comment(_)
+    );
+    match next.map(|next| &next.kind) {
+        None => false,
+        Some(NodeKind::Text(text)) => {
+            !silent && !text.starts_with(['.', ',', ';', ':', '!', '?', ')'])
+        }
+        Some(_) => !silent,
+    }
+}
+
+/// Check that an address is safe to put in an href or src attribute.
+/// Absolute addresses must use a web or mail scheme.
+pub(crate) fn check_address(address: &str) -> Result<(), String> {
+    if let Some(c) = address
+        .chars()
+        .find(|c| c.is_whitespace() || matches!(c, '"' | '<' | '>' | '\\' | '`'))
+    {
+        return Err(format!("'{}' is not allowed in an address", c));
+    }
+
+    if let Some(scheme) = scheme(address) {
+        if !matches!(scheme.as_str(), "http" | "https" | "mailto" | "ftp") {
+            return Err(format!("unsupported scheme '{}'", scheme));
+        }
+    }
+    Ok(())
+}
+
+/// The lower-cased scheme of an absolute address: whatever precedes the
+/// first ':' if no '/' comes before it
+fn scheme(address: &str) -> Option<String> {
+    let colon = address.find(':')?;
+    if address[..colon].contains('/') {
+        return None;
+    }
+    Some(address[..colon].to_lowercase())
+}
+
+/// True if `text` contains `pattern`, ignoring ASCII case
+fn contains_ignore_case(text: &str, pattern: &str) -> bool {
+    text.to_ascii_lowercase().contains(&pattern.to_ascii_lowercase())
+}
+
+fn has_scheme(address: &str) -> bool {
+    scheme(address).is_some()
+}
+
+/// Check a #GIMMEH LINX address and return the href to emit.
+///
+/// Relative links to another .lol page (in any case) are pointed at the
+/// .html file the compiler produces for it, keeping any ?query and
+/// #fragment part.
+fn link_target(address: &str) -> Result<String, String> {
+    check_address(address)?;
+    if has_scheme(address) {
+        return Ok(address.to_string());
+    }
+
+    let (path, rest) = match address.find(['?', '#']) {
+        Some(i) => address.split_at(i),
+        None => (address, ""),
+    };
+    let stem = path.len().checked_sub(4).filter(|&i| path.is_char_boundary(i));
+    match stem {
+        Some(i) if path[i..].eq_ignore_ascii_case(".lol") => {
+            Ok(format!("{}.html{}", &path[..i], rest))
+        }
+        _ => Ok(address.to_string()),
+    }
+}
+
+/// `<nav>` holding nested ordered lists of links, one list level per
+/// heading level. A heading deeper than the one before it opens a sublist
+/// inside that heading's item.
+fn table_of_contents(headings: &[HeadingEntry], classes: &Classes) -> String {
+    let mut html = classes.open("nav", "") + "\n";
+    // Heading levels of the <ol>s currently open, outermost first
+    let mut open: Vec<u8> = Vec::new();
+
+    for heading in headings {
+        match open.last() {
+            None => {
+                html.push_str(&classes.open("ol", ""));
+                html.push('\n');
+                open.push(heading.level);
+            }
+            Some(&top) if heading.level > top => {
+                html.push('\n');
+                html.push_str(&classes.open("ol", ""));
+                html.push('\n');
+                open.push(heading.level);
+            }
+            Some(_) => {
+                html.push_str("</li>\n");
+                while open.len() > 1 && open.last().is_some_and(|&top| heading.level < top) {
+                    open.pop();
+                    html.push_str("</ol>\n</li>\n");
+                }
+                // A heading shallower than the first one becomes the new
+                // top level, so deeper headings after it nest under it
+                if open.len() == 1 && heading.level < open[0] {
+                    open[0] = heading.level;
+                }
+            }
+        }
+        html.push_str(&classes.open("li", ""));
+        html.push_str(&classes.open("a", &format!(" href=\"#{}\"", heading.id)));
+        html.push_str(&escape_html(&heading.text));
+        html.push_str("</a>");
+    }
+
+    html.push_str("</li>\n");
+    while open.pop().is_some() {
+        html.push_str("</ol>\n");
+        if !open.is_empty() {
+            html.push_str("</li>\n");
+        }
+    }
+    html.push_str("</nav>\n");
+    html
+}
+
+/// Turn heading text into an anchor id: lower case letters and digits,
+/// with every other run of characters collapsed into one '-'
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// The words of some inline content without any markup; variable uses
+/// are skipped
+fn text_content(nodes: &[Node]) -> String {
+    let mut words = Vec::new();
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Text(text) => words.push(text.clone()),
+            NodeKind::Link {
+                address,
+                label: None,
+            } => words.push(address.clone()),
+            _ => words.push(text_content(node.children())),
+        }
+    }
+    words.retain(|word| !word.is_empty());
+    words.join(" ")
+}
+
+/// Escape text for use inside an HTML attribute value or element
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
     }
+    escaped
 }
@@ -0,0 +1,19 @@
+/// Stylesheets built into the binary, selected with --theme
+const THEMES: &[(&str, &str)] = &[
+    ("plain", include_str!("themes/plain.css")),
+    ("dark", include_str!("themes/dark.css")),
+    ("springfield", include_str!("themes/springfield.css")),
+];
+
+/// The CSS of the built-in theme called `name`
+pub fn stylesheet(name: &str) -> Option<&'static str> {
+    THEMES
+        .iter()
+        .find(|(theme, _)| theme.eq_ignore_ascii_case(name))
+        .map(|(_, css)| *css)
+}
+
+/// Names of the built-in themes, for help and error messages
+pub fn names() -> Vec<&'static str> {
+    THEMES.iter().map(|(name, _)| *name).collect()
+}
@@ -0,0 +1,59 @@
+//! Every test/*.lol compiled through the library and compared with the
+//! files next to it: TestN.html holds the expected output and TestN.err
+//! the expected diagnostics, one `file:line:column: message` per line as
+//! the command line reports them when run from test/. Without a .err file
+//! the fixture must compile without any.
+
+use std::fs;
+use std::path::Path;
+
+use lolcompiler::compiler::LolCompiler;
+use lolcompiler::diagnostic::{Diagnostic, LineIndex};
+
+/// Diagnostics in the command line's report format
+fn report(name: &str, source: &str, diagnostics: &[Diagnostic]) -> String {
+    let lines = LineIndex::new(source);
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        let (line, column) = lines.line_col(diagnostic.span.start);
+        out.push_str(&format!("{}:{}:{}: {}\n", name, line + 1, column + 1, diagnostic));
+    }
+    out
+}
+
+#[test]
+fn fixtures_match_expected_output() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+    let mut sources: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lol"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty(), "no fixtures in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in &sources {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(path).unwrap();
+        let mut compiler = LolCompiler::new();
+        compiler.set_source_file(path);
+        compiler.analyze(&source);
+
+        // Messages naming files, like include cycles, spell them from test/
+        let reported = report(&name, &source, compiler.diagnostics())
+            .replace(&format!("{}/", dir.display()), "");
+        let expected = fs::read_to_string(path.with_extension("err")).unwrap_or_default();
+        if reported != expected {
+            failures.push(format!("{}: expected diagnostics\n{}got\n{}", name, expected, reported));
+        }
+
+        let html = path.with_extension("html");
+        if let Ok(expected) = fs::read_to_string(&html) {
+            if compiler.semantic().get_output() != expected {
+                failures.push(format!("{}: output differs from {}", name, html.display()));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,74 @@
+//! Fixtures in test/options that are compiled with command-line options.
+//! Each is copied to a scratch directory, compiled there and compared with
+//! the expected HTML next to it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tells apart the scratch directories of tests running in parallel
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("test/options").join(name)
+}
+
+/// Compile fixture `name` with `args` and return its HTML, or the error
+/// output if compilation fails
+fn compile(name: &str, args: &[&str]) -> Result<String, String> {
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let scratch = format!("lolcompiler-options-{}-{}", std::process::id(), run);
+    let dir = std::env::temp_dir().join(scratch);
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join(format!("{}.lol", name));
+    fs::copy(fixture(&format!("{}.lol", name)), &input).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lolcompiler"))
+        .args(args)
+        .arg(&input)
+        .output()
+        .unwrap();
+    let result = if output.status.success() {
+        Ok(fs::read_to_string(dir.join(format!("{}.html", name))).unwrap())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+    fs::remove_dir_all(&dir).unwrap();
+    result
+}
+
+#[test]
+fn theme_classes_and_linked_stylesheet() {
+    let args = [
+        "--theme",
+        "dark",
+        "--class",
+        "p=note",
+        "--class",
+        "li=item special",
+        "--css-link",
+        "css/extra.css",
+    ];
+    let expected = fs::read_to_string(fixture("Styled.html")).unwrap();
+    assert_eq!(compile("Styled", &args).unwrap(), expected);
+}
+
+#[test]
+fn linked_stylesheet_address_is_checked() {
+    let error = compile("Styled", &["--css-link", "javascript:alert(1)"]).unwrap_err();
+    assert!(error.contains("Invalid stylesheet address 'javascript:alert(1)'"), "{}", error);
+    assert!(error.contains("unsupported scheme 'javascript'"), "{}", error);
+
+    let error = compile("Styled", &["--css-link", "a\"b.css"]).unwrap_err();
+    assert!(error.contains("'\"' is not allowed in an address"));
+}
+
+#[test]
+fn unknown_theme_and_class_element_are_rejected() {
+    let error = compile("Styled", &["--theme", "neon"]).unwrap_err();
+    assert!(error.contains("Unknown theme 'neon'"));
+
+    let error = compile("Styled", &["--class", "div=box"]).unwrap_err();
+    assert!(error.contains("Cannot set a class on 'div'"));
+}